@@ -3,18 +3,20 @@ extern crate vdb;
 use std::io;
 use std::io::Write;
 
-use vdb::{Db, Entry};
+use vdb::{Db, Entry, Predicate, RowBuilder};
 
 fn list_entries(db: &mut Db) {
-    let row_ids = db.find_row_ids_by_name("title");
-    let entries = db.entries_from_row_ids(&row_ids, &["title", "text"]);
-    if entries.is_empty() {
+    let rows = db.page_sorted(&[Predicate::new_any("title")], "title", 0, usize::MAX);
+    if rows.is_empty() {
         println!();
         println!("No entries.");
     } else {
-        for entry in &entries {
-            if entry.len() >= 2 {
-                println!("{}: {}", entry[0].value, entry[1].value);
+        for row in &rows {
+            if let (Some(title), Some(text)) = (
+                Entry::get_first_by_name_ref(row, "title"),
+                Entry::get_first_by_name_ref(row, "text"),
+            ) {
+                println!("{}: {}", title.value, text.value);
             }
         }
     }
@@ -37,10 +39,11 @@ fn new_entry(db: &mut Db) {
         let mut input = "".to_string();
         let _bytes_read = io::stdin().read_line(&mut input).unwrap();
         let text = input.trim();
-        db.add_row(vec![
-            Entry::new_string("title", title),
-            Entry::new_string("text", text),
-        ]);
+        db.add_row_builder(
+            RowBuilder::new()
+                .string("title", title)
+                .string("text", text),
+        );
     } else {
         println!("Abort.");
     }
@@ -58,7 +61,8 @@ fn delete_entry(db: &mut Db) {
     };
     if !title.is_empty() {
         let row_ids = db.find_row_ids_by_value("title", &Db::db_string(title));
-        db.delete_rows(&row_ids);
+        let deleted = db.delete_rows(&row_ids);
+        println!("Deleted {} entries.", deleted);
     } else {
         println!("Abort.");
     }
@@ -87,7 +91,7 @@ fn main_loop(db: &mut Db) {
             "e" => new_entry(db),
             "d" => delete_entry(db),
             "" | "q" => {
-                let _ = db.save();
+                let _ = db.save_if_dirty();
                 break;
             }
             _ => (),
@@ -99,10 +103,7 @@ fn main_loop(db: &mut Db) {
 
 fn main() {
     let db_name = "notebook";
-    let mut db = if let Ok(db) = Db::load(db_name) {
-        db
-    } else {
-        Db::new(db_name)
-    };
+    let mut db = Db::load_or_new(db_name).expect("Could not load notebook");
+    println!("{} entries in notebook.", db.row_count());
     main_loop(&mut db);
 }
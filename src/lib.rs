@@ -1,75 +1,250 @@
+//! A basic database system to store key/value pairs with few dependencies.
+//!
+//! # Examples
+//!
+//! ```
+//! use vdb::{Db, Entry, Predicate};
+//! let mut db = Db::new("test-db");
+//! let row_1 = db.add_row(vec![
+//!         Entry::new_string("word", "cocina"),
+//!         Entry::new_string("translation", "cuisine"),
+//!         Entry::new_string("translation", "kitchen"),
+//! ]);
+//! let row_2 = db.add_row(vec![
+//!         Entry::new_string("word", "coche"),
+//!         Entry::new_string("translation", "car"),
+//! ]);
+//!
+//! // Load and save
+//! db.save();
+//! let mut new_db = Db::load("test-db").unwrap();
+//! let row_ids = new_db.find_all_row_ids();
+//! assert_eq!(row_ids.len(), 2);
+//!
+//! // Find rows
+//! let row_ids = db.find_row_ids_by_predicate(&vec![Predicate::new_equal_string("word", "coche")], None);
+//! assert_eq!(row_ids, [row_2]);
+//! let entries = db.entries_from_row_ids(&row_ids, &["translation"]);
+//! assert_eq!(entries[0][0], Entry::new_string("translation", "car"));
+//!
+//! // Delete
+//! let coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
+//! assert_eq!(coche, Some(row_2));
+//! db.delete_rows(&[row_1, row_2]);
+//! let no_coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
+//! assert_eq!(no_coche, None);
+//! ```
+
+#[cfg(feature = "bincode")]
+extern crate bincode;
 extern crate chrono;
+#[cfg(feature = "flate2")]
+extern crate flate2;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
 
 //use chrono::{DateTime, Duration, Utc};
-use chrono::{Local, NaiveDateTime};
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+#[cfg(feature = "flate2")]
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::BufRead;
 use std::io::Read;
 use std::io::Write;
+use std::ops::Deref;
 use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
-/// A basic database system to store key/value pairs with few dependencies.
-///
-/// # Examples
-///
-/// ```
-/// use vdb::{Db, Entry, Predicate};
-/// let mut db = Db::new("test-db");
-/// let row_1 = db.add_row(vec![
-///         Entry::new_string("word", "cocina"),
-///         Entry::new_string("translation", "cuisine"),
-///         Entry::new_string("translation", "kitchen"),
-/// ]);
-/// let row_2 = db.add_row(vec![
-///         Entry::new_string("word", "coche"),
-///         Entry::new_string("translation", "car"),
-/// ]);
-///
-/// // Load and save
-/// db.save();
-/// let mut new_db = Db::load("test-db").unwrap();
-/// let row_ids = new_db.find_all_row_ids();
-/// assert_eq!(row_ids.len(), 2);
-///
-/// // Find rows
-/// let row_ids = db.find_row_ids_by_predicate(&vec![Predicate::new_equal_string("word", "coche")], None);
-/// assert_eq!(row_ids, [row_2]);
-/// let entries = db.entries_from_row_ids(&row_ids, &["translation"]);
-/// assert_eq!(entries[0][0], Entry::new_string("translation", "car"));
-///
-/// // Delete
-/// let coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
-/// assert_eq!(coche, Some(row_2));
-/// db.delete_rows(&[row_1, row_2]);
-/// let no_coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
-/// assert_eq!(no_coche, None);
-/// ```
+/// Directory where databases are loaded from and saved to
+const SAVE_DIR: &str = "save";
+
+/// Name of the entry `Db::enable_timestamps()` adds to new rows
+pub const CREATED_AT: &str = "__created_at__";
+
+/// First bytes of a gzip compressed file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Errors returned by database operations
+#[derive(Debug)]
+pub enum DbError {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// The database file could not be serialized or parsed
+    Serde(serde_json::Error),
+    /// A string could not be parsed as a `DbDateTime`
+    DateTimeParse(chrono::ParseError),
+    /// A string matched none of the formats tried by `Db::db_datetime_flexible()`
+    DateTimeFormats {
+        value: String,
+        tried: Vec<&'static str>,
+    },
+    /// Malformed CSV data
+    Csv(String),
+    /// A row given to `Db::add_row_checked()` holds more than one entry with this name
+    DuplicateName(String),
+    /// A JSON Lines record could not be parsed, `line` counts from 1
+    Jsonl {
+        line: usize,
+        error: serde_json::Error,
+    },
+    /// The binary database file could not be serialized or parsed. Only returned with the
+    /// `bincode` feature.
+    Bincode(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::Io(e) => write!(f, "I/O error: {}", e),
+            DbError::Serde(e) => write!(f, "Serialization error: {}", e),
+            DbError::DateTimeParse(e) => write!(f, "Could not parse datetime: {}", e),
+            DbError::DateTimeFormats { value, tried } => write!(
+                f,
+                "Could not parse datetime {:?}, tried formats: {}",
+                value,
+                tried.join(", ")
+            ),
+            DbError::Csv(e) => write!(f, "Could not parse CSV: {}", e),
+            DbError::DuplicateName(name) => {
+                write!(f, "Entry name {:?} must be unique in a row", name)
+            }
+            DbError::Jsonl { line, error } => {
+                write!(
+                    f,
+                    "Could not parse JSON Lines record on line {}: {}",
+                    line, error
+                )
+            }
+            DbError::Bincode(e) => write!(f, "Binary serialization error: {}", e),
+        }
+    }
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Io(e) => Some(e),
+            DbError::Serde(e) => Some(e),
+            DbError::DateTimeParse(e) => Some(e),
+            DbError::DateTimeFormats { .. } => None,
+            DbError::Csv(_) => None,
+            DbError::DuplicateName(_) => None,
+            DbError::Jsonl { error, .. } => Some(error),
+            DbError::Bincode(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for DbError {
+    fn from(e: io::Error) -> DbError {
+        DbError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> DbError {
+        DbError::Serde(e)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for DbError {
+    fn from(e: bincode::Error) -> DbError {
+        DbError::Bincode(e)
+    }
+}
+
+impl From<chrono::ParseError> for DbError {
+    fn from(e: chrono::ParseError) -> DbError {
+        DbError::DateTimeParse(e)
+    }
+}
+
+/// Format used by `Db::db_datetime()` to parse and by `Display` to print a `DbDateTime`
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 /// Data types currently implemented in the database
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub enum Data {
     DbString(String),
+    /// Kept for existing databases, prefer `DbI64` in new code
     DbI32(i32),
     DbDateTime(NaiveDateTime),
+    DbI64(i64),
+    /// Small binary values like audio clips. Stored as base64 in JSON.
+    DbBytes(#[serde(with = "base64")] Vec<u8>),
 }
 
+/// `DbDateTime`s are printed as `%Y-%m-%d %H:%M:%S`, the format `Db::db_datetime()` parses.
+/// Use `Data::format_with()` for other formats. `DbBytes` only print their length.
 impl fmt::Display for Data {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match self {
-            Data::DbDateTime(date_time) => date_time.format("%Y-%m-%d %H:%M").to_string(),
+            Data::DbDateTime(date_time) => date_time.format(DATETIME_FORMAT).to_string(),
             Data::DbI32(number) => format!("{}", number),
+            Data::DbI64(number) => format!("{}", number),
             Data::DbString(string) => string.clone(),
+            Data::DbBytes(bytes) => format!("<{} bytes>", bytes.len()),
         };
         write!(f, "{}", printable)
     }
 }
 
+/// Data of any type can be compared, so `Data` can be sorted and used as a `BTreeMap` key.
+/// Different types are ordered `DbI32` < `DbI64` < `DbString` < `DbDateTime` < `DbBytes`, so a
+/// `DbI32` is always less than a `DbI64`, whatever the numbers. Values of the same type are
+/// ordered by their natural order: numbers numerically, strings and bytes lexically by bytes and
+/// datetimes chronologically.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::Db;
+/// let mut data = vec![Db::db_string("a"), Db::db_i32(2), Db::db_i32(-1)];
+/// data.sort();
+/// assert_eq!(data, [Db::db_i32(-1), Db::db_i32(2), Db::db_string("a")]);
+/// ```
+impl Ord for Data {
+    fn cmp(&self, other: &Data) -> Ordering {
+        fn rank(data: &Data) -> u8 {
+            match data {
+                Data::DbI32(_) => 0,
+                Data::DbI64(_) => 1,
+                Data::DbString(_) => 2,
+                Data::DbDateTime(_) => 3,
+                Data::DbBytes(_) => 4,
+            }
+        }
+        match (self, other) {
+            (Data::DbI32(left), Data::DbI32(right)) => left.cmp(right),
+            (Data::DbI64(left), Data::DbI64(right)) => left.cmp(right),
+            (Data::DbString(left), Data::DbString(right)) => left.cmp(right),
+            (Data::DbDateTime(left), Data::DbDateTime(right)) => left.cmp(right),
+            (Data::DbBytes(left), Data::DbBytes(right)) => left.cmp(right),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+impl PartialOrd for Data {
+    fn partial_cmp(&self, other: &Data) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Data {
     /// Tests if the data starts with the given string
     fn starts_with(&self, data: &Data) -> bool {
@@ -89,11 +264,40 @@ impl Data {
         }
     }
 
+    /// Returns the type of the data
+    pub fn kind(&self) -> DataKind {
+        match self {
+            Data::DbString(_) => DataKind::String,
+            Data::DbI32(_) => DataKind::I32,
+            Data::DbI64(_) => DataKind::I64,
+            Data::DbDateTime(_) => DataKind::DateTime,
+            Data::DbBytes(_) => DataKind::Bytes,
+        }
+    }
+
     /// Returns new DbDateTime with current time as timestamp
     pub fn now() -> Data {
         Data::DbDateTime(Local::now().naive_local())
     }
 
+    /// Format a `DbDateTime` with a chrono format string. Returns `None` for other types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let data = Db::db_datetime("2018-01-01 10:30:15").unwrap();
+    /// assert_eq!(data.format_with("%H:%M"), Some(String::from("10:30")));
+    /// assert_eq!(Db::db_i32(1).format_with("%H:%M"), None);
+    /// ```
+    pub fn format_with(&self, fmt: &str) -> Option<String> {
+        if let Data::DbDateTime(d) = self {
+            Some(d.format(fmt).to_string())
+        } else {
+            None
+        }
+    }
+
     pub fn date(&self) -> Option<String> {
         if let Data::DbDateTime(d) = self {
             Some(d.format("%Y-%m-%d").to_string())
@@ -103,9 +307,40 @@ impl Data {
     }
 }
 
+/// The type of a `Data` value without the value itself
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum DataKind {
+    String,
+    I32,
+    DateTime,
+    I64,
+    Bytes,
+}
+
+/// An entry whose type does not match the schema given to `Db::validate_schema()`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SchemaError {
+    pub row_id: RowId,
+    pub name: String,
+    pub expected: DataKind,
+    pub found: DataKind,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: entry \"{}\" should be {:?}, found {:?}",
+            self.row_id, self.name, self.expected, self.found
+        )
+    }
+}
+
 /// The Row Identifier is used to reference each data set and is used by many methods where the
 /// actual data is not used directly.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug, Copy, PartialOrd, Ord)]
+#[derive(
+    Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug, Copy, PartialOrd, Ord, Default,
+)]
 pub struct RowId(pub usize);
 
 /// Each RowId has many entries. Comparable to column name+data in relational databases.
@@ -132,6 +367,35 @@ impl Entry {
         }
     }
 
+    /// Shortcut for creating a new `Entry` with a `DbI64`
+    pub fn new_i64(name: &str, value: i64) -> Entry {
+        Entry {
+            name: String::from(name),
+            value: Db::db_i64(value),
+        }
+    }
+
+    /// Shortcut for creating a new `Entry` with a `DbDateTime`
+    pub fn new_datetime(name: &str, value: NaiveDateTime) -> Entry {
+        Entry {
+            name: String::from(name),
+            value: Data::DbDateTime(value),
+        }
+    }
+
+    /// Shortcut for creating a new `Entry` with a `DbDateTime` parsed from `value`. The format is
+    /// the same as for `Db::db_datetime()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::DateTimeParse` if `value` can not be parsed.
+    pub fn new_datetime_str(name: &str, value: &str) -> Result<Entry, DbError> {
+        Ok(Entry {
+            name: String::from(name),
+            value: Db::db_datetime(value)?,
+        })
+    }
+
     /// # Examples
     ///
     /// ```
@@ -154,6 +418,16 @@ impl Entry {
             PredicateType::Contains => {
                 self.name == predicate.entry.name && self.value.contains(&predicate.entry.value)
             }
+            PredicateType::In(values) => {
+                self.name == predicate.entry.name && values.contains(&self.value)
+            }
+            PredicateType::EqualFolded => {
+                self.name == predicate.entry.name
+                    && match (&self.value, &predicate.entry.value) {
+                        (Data::DbString(value), Data::DbString(folded)) => fold(value) == *folded,
+                        _ => false,
+                    }
+            }
         }
     }
 
@@ -186,36 +460,100 @@ impl Entry {
         false
     }
 
-    /// Return first `Entry` in a given list that matches `name`
+    /// Return a copy of the first `Entry` in a given list that matches `name`. Use
+    /// `get_first_by_name_ref()` to avoid the clone.
     pub fn get_first_by_name(entries: &[Entry], name: &str) -> Option<Entry> {
-        for entry in entries {
-            if entry.name == name {
-                return Some(entry.clone());
-            }
-        }
-        None
+        Entry::get_first_by_name_ref(entries, name).cloned()
+    }
+
+    /// Return first `Entry` in a given list that matches `name` as reference
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Entry;
+    /// let entries = vec![Entry::new_string("word", "coche"), Entry::new_string("value", "car")];
+    /// assert_eq!(Entry::get_first_by_name_ref(&entries, "value"), Some(&entries[1]));
+    /// ```
+    pub fn get_first_by_name_ref<'a>(entries: &'a [Entry], name: &str) -> Option<&'a Entry> {
+        entries.iter().find(|entry| entry.name == name)
     }
 
     /// Return first `Entry` in a given list that matches `name` as mutable reference
     pub fn get_first_by_name_mut<'a>(
-        entries: &'a mut Vec<Entry>,
+        entries: &'a mut [Entry],
         name: &str,
     ) -> Option<&'a mut Entry> {
-        for entry in entries {
-            if entry.name == name {
-                return Some(entry);
-            }
-        }
-        None
+        entries.iter_mut().find(|entry| entry.name == name)
+    }
+}
+
+/// Builds the entries of a row step by step.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::{Db, RowBuilder};
+/// let mut db = Db::new("test-db");
+/// let row_id = db.add_row_builder(
+///     RowBuilder::new()
+///         .string("word", "coche")
+///         .string("translation", "car")
+///         .i32("add_counter", 1),
+/// );
+/// assert_eq!(db.get_i32(row_id, "add_counter"), Some(1));
+/// ```
+#[derive(Default, Clone, Debug)]
+pub struct RowBuilder {
+    entries: Vec<Entry>,
+}
+
+impl RowBuilder {
+    pub fn new() -> RowBuilder {
+        RowBuilder::default()
+    }
+
+    /// Add an entry with a `DbString`
+    pub fn string(mut self, name: &str, value: &str) -> RowBuilder {
+        self.entries.push(Entry::new_string(name, value));
+        self
+    }
+
+    /// Add an entry with a `DbI32`
+    pub fn i32(mut self, name: &str, value: i32) -> RowBuilder {
+        self.entries.push(Entry::new_i32(name, value));
+        self
+    }
+
+    /// Add an entry with a `DbI64`
+    pub fn i64(mut self, name: &str, value: i64) -> RowBuilder {
+        self.entries.push(Entry::new_i64(name, value));
+        self
+    }
+
+    /// Add an entry with a `DbDateTime`
+    pub fn datetime(mut self, name: &str, value: NaiveDateTime) -> RowBuilder {
+        self.entries.push(Entry::new_datetime(name, value));
+        self
+    }
+
+    /// Returns the entries in the order they were added
+    pub fn build(self) -> Vec<Entry> {
+        self.entries
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum PredicateType {
     Equal,
     StartsWith,
     Contains,
     Any,
+    /// Matches if the value equals any of the listed values
+    In(Vec<Data>),
+    /// Matches `DbString`s equal to the value ignoring case and diacritics. Diacritics are only
+    /// ignored with the `unicode-normalization` feature.
+    EqualFolded,
 }
 
 /// Used to compare database entries, e. g. in queries (fn find_*)
@@ -231,7 +569,7 @@ pub enum PredicateType {
 /// assert_eq!(a.compare(&Predicate::new_contains("mundo", "orl")), true);
 /// assert_eq!(a.compare(&Predicate::new_equal_string("mundo", "planet")), false);
 /// ```
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Predicate {
     pub predicate_type: PredicateType,
     pub entry: Entry,
@@ -249,9 +587,38 @@ impl Predicate {
         }
     }
 
-    /// Shortcut for creating a new `Predicate` that searches database for `DbString`s equal to
-    /// `value`
-    pub fn new_any_string(name: &str) -> Predicate {
+    /// Shortcut for creating a new `Predicate` that tests for equality with a `DbI64`
+    pub fn new_equal_i64(name: &str, value: i64) -> Predicate {
+        Predicate {
+            predicate_type: PredicateType::Equal,
+            entry: Entry {
+                name: String::from(name),
+                value: Db::db_i64(value),
+            },
+        }
+    }
+
+    /// Shortcut for creating a new `Predicate` that tests for equality with a `DbDateTime`. Like
+    /// all equality predicates it is answered from the value index.
+    pub fn new_equal_datetime(name: &str, value: NaiveDateTime) -> Predicate {
+        Predicate {
+            predicate_type: PredicateType::Equal,
+            entry: Entry::new_datetime(name, value),
+        }
+    }
+
+    /// Shortcut for creating a new `Predicate` that matches any entry named `name`, whatever the
+    /// type of its value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Entry, Predicate};
+    /// let predicate = Predicate::new_any("add_counter");
+    /// assert!(Entry::new_i32("add_counter", 3).compare(&predicate));
+    /// assert!(!Entry::new_i32("remove_counter", 3).compare(&predicate));
+    /// ```
+    pub fn new_any(name: &str) -> Predicate {
         Predicate {
             predicate_type: PredicateType::Any,
             entry: Entry {
@@ -261,6 +628,12 @@ impl Predicate {
         }
     }
 
+    /// Shortcut for creating a new `Predicate` that matches any entry named `name`. Same as
+    /// `new_any()`, the type of the value is not checked.
+    pub fn new_any_string(name: &str) -> Predicate {
+        Predicate::new_any(name)
+    }
+
     /// Shortcut for creating a new `Predicate` that searches database for `DbString`s equal to
     /// `value`
     pub fn new_equal_string(name: &str, value: &str) -> Predicate {
@@ -295,816 +668,6678 @@ impl Predicate {
             },
         }
     }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
-struct Row {
-    pub row_id: RowId,
-    pub entry: Entry,
+    /// Shortcut for creating a new `Predicate` that searches database for `DbString`s equal to
+    /// `value` ignoring case and diacritics, so "nino" finds "Niño". Requires the
+    /// `unicode-normalization` feature. Unlike `new_equal_string()` this can not use the index
+    /// and scans all rows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "unicode-normalization")]
+    /// # {
+    /// use vdb::{Entry, Predicate};
+    /// let predicate = Predicate::new_equal_folded("word", "nino");
+    /// assert!(Entry::new_string("word", "niño").compare(&predicate));
+    /// assert!(Entry::new_string("word", "NIÑO").compare(&predicate));
+    /// assert!(!Entry::new_string("word", "niña").compare(&predicate));
+    /// # }
+    /// ```
+    #[cfg(feature = "unicode-normalization")]
+    pub fn new_equal_folded(name: &str, value: &str) -> Predicate {
+        Predicate {
+            predicate_type: PredicateType::EqualFolded,
+            entry: Entry {
+                name: String::from(name),
+                value: Db::db_string(&fold(value)),
+            },
+        }
+    }
+
+    /// Shortcut for creating a new `Predicate` that searches database for `DbString`s equal to
+    /// any of `values`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Entry, Predicate};
+    /// let predicate = Predicate::new_in_strings("word", &["coche", "moto", "bici"]);
+    /// assert!(Entry::new_string("word", "moto").compare(&predicate));
+    /// assert!(!Entry::new_string("word", "barco").compare(&predicate));
+    /// ```
+    pub fn new_in_strings(name: &str, values: &[&str]) -> Predicate {
+        Predicate {
+            predicate_type: PredicateType::In(values.iter().map(|v| Db::db_string(v)).collect()),
+            entry: Entry {
+                name: String::from(name),
+                value: Db::db_string(""),
+            },
+        }
+    }
 }
 
-/// Container for the database. Usually only one is used per application.
+/// Filter for `Db::run()` built from predicates combined with `and()` and `or()`. Operators
+/// apply to everything built so far, left to right: `a.or(b).and(c)` means `(a or b) and c`.
+/// The first predicate simply starts the query, whether it is added with `and()` or `or()`. An
+/// empty query matches all rows.
 ///
 /// # Examples
 ///
 /// ```
-/// use vdb::{Db, Entry};
+/// use vdb::{Db, Entry, Predicate, Query};
 /// let mut db = Db::new("test-db");
-/// let _row_id = db.add_row(vec![Entry::new_string("mundo", "world")]);
+/// let coche = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("name", "coche")]);
+/// let auto = db.add_row(vec![Entry::new_string("set", "de-en"), Entry::new_string("name", "Auto")]);
+/// let _moto = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("name", "moto")]);
+/// let query = Query::new()
+///     .or(Predicate::new_equal_string("name", "coche"))
+///     .or(Predicate::new_equal_string("name", "Auto"))
+///     .and(Predicate::new_any("set"));
+/// assert_eq!(db.run(&query), [coche, auto]);
 /// ```
-#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
-pub struct Db {
-    full_filename: String,
-    row_max: RowId,
-    by_row_id: HashMap<RowId, Vec<Entry>>,
-    by_name: HashMap<String, HashSet<RowId>>,
-    by_value: HashMap<Entry, HashSet<RowId>>,
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    /// Rows match if they match all predicates of at least one group
+    groups: Vec<Vec<Predicate>>,
+    limit: Option<usize>,
 }
 
-impl Db {
-    /// Create new database in memory. The file is not created until `save()` is called.
-    pub fn new(filename: &str) -> Db {
-        Db {
-            full_filename: Db::build_filename(filename),
-            row_max: RowId(0),
-            by_row_id: HashMap::new(),
-            by_name: HashMap::new(),
-            by_value: HashMap::new(),
-        }
+impl Query {
+    pub fn new() -> Query {
+        Query::default()
     }
 
-    /// Load a database file from the filesystem under the subdirectory `save/`.
-    ///
-    /// # Errors
-    ///
-    /// May return errors from external modules while opening the file or parsing the contents.
-    pub fn load(filename: &str) -> Result<Db, Box<Error>> {
-        let full_filename = Db::build_filename(filename);
-        let mut file = File::open(full_filename)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let mut db = Db::new(filename);
-        let row_id_map: HashMap<RowId, Vec<Entry>> = serde_json::from_str(&contents)?;
-        for (_row_id, entries) in row_id_map {
-            db.add_row(entries);
+    /// Rows must match the query so far and `predicate`
+    pub fn and(mut self, predicate: Predicate) -> Query {
+        if self.groups.is_empty() {
+            self.groups.push(vec![predicate]);
+        } else {
+            for group in &mut self.groups {
+                group.push(predicate.clone());
+            }
         }
-        Ok(db)
+        self
     }
 
-    /// Save database under the subdirectory `save/` with the same name it was `open`ed or `create`d
-    /// with. The subdirectory `save/` must exist.
-    pub fn save(&mut self) -> Result<(), Box<Error>> {
-        self.by_row_id.retain(|_key, value| !value.is_empty());
-        let path = Path::new(&self.full_filename);
-        let mut file = File::create(&path)?;
-        let serialized = match serde_json::to_string_pretty(&self.by_row_id) {
-            Ok(s) => s,
-            Err(ref e) => {
-                println!("{}|{}", e.description(), e);
-                panic!()
-            }
-        };
-        file.write_all(serialized.as_bytes())?;
-        Ok(())
+    /// Rows must match the query so far or `predicate`
+    pub fn or(mut self, predicate: Predicate) -> Query {
+        self.groups.push(vec![predicate]);
+        self
     }
 
-    /// Returns the filename of the database
-    pub fn get_name(&self) -> String {
-        // TODO: This assumes that the save prefix is "save/"
-        if self.full_filename.len() < 6 {
-            panic!("Could not Db::get_name()");
+    /// Rows must match the query so far and `query`. The limit of `query` is ignored, combining
+    /// with an empty query changes nothing.
+    pub fn and_query(mut self, query: Query) -> Query {
+        if self.groups.is_empty() {
+            self.groups = query.groups;
+        } else if !query.groups.is_empty() {
+            let mut groups = Vec::with_capacity(self.groups.len() * query.groups.len());
+            for group in &self.groups {
+                for other in &query.groups {
+                    groups.push(group.iter().chain(other).cloned().collect());
+                }
+            }
+            self.groups = groups;
         }
-        self.full_filename[5..].to_string()
+        self
     }
 
-    /// Returns a new Data::DbString
-    pub fn db_string(v: &str) -> Data {
-        Data::DbString(String::from(v))
+    /// Rows must match the query so far or `query`. The limit of `query` is ignored, combining
+    /// with an empty query changes nothing.
+    pub fn or_query(mut self, query: Query) -> Query {
+        self.groups.extend(query.groups);
+        self
     }
 
-    /// Returns a new Data::DbI32
-    pub fn db_i32(v: i32) -> Data {
-        Data::DbI32(v)
+    /// Return at most `limit` rows, the ones with the lowest RowIds
+    pub fn limit(mut self, limit: usize) -> Query {
+        self.limit = Some(limit);
+        self
     }
+}
 
-    /// Find a i32 by name
-    /// ```
-    /// use vdb::{Db, Entry};
-    /// let mut db = Db::new("test-db");
-    /// let name = "yoyo";
-    /// let value = 7;
-    /// let _row_id = db.add_row(vec![Entry::new_i32(name, value)]);
-    /// assert_eq!(db.find_first_i32(name), Some(value));
-    /// ```
-    pub fn find_first_i32(&self, name: &str) -> Option<i32> {
-        if let Some(row_id) = self.find_first_row_id_by_name(name) {
-            if let Some(entries) = self.by_row_id.get(&row_id) {
-                if let Some(entry) = Entry::get_first_by_name(entries, name) {
-                    if let Data::DbI32(value) = entry.value {
-                        return Some(value);
-                    }
-                }
-            }
-        }
-        None
+/// One line of `Db::export_jsonl()` and `Db::import_jsonl()`
+#[derive(Serialize, Deserialize)]
+struct JsonlRow<'a> {
+    row_id: RowId,
+    entries: Cow<'a, [Entry]>,
+}
+
+/// Layout of a saved database. `row_max` is saved next to the rows so RowIds of deleted rows are
+/// not handed out again after loading.
+#[derive(Serialize, Deserialize)]
+struct SavedDb<R> {
+    rows: R,
+    #[serde(default)]
+    row_max: RowId,
+}
+
+/// Contents of a JSON database file. Older files only hold the map of rows, so both layouts are
+/// accepted.
+struct SavedFile(SavedDb<HashMap<RowId, Vec<Entry>>>);
+
+impl<'de> serde::Deserialize<'de> for SavedFile {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<SavedFile, D::Error> {
+        deserializer.deserialize_map(SavedFileVisitor)
     }
+}
 
-    /// Find a string by name
-    /// ```
-    /// use vdb::{Db, Entry};
-    /// let mut db = Db::new("test-db");
-    /// let name = "yoyo";
-    /// let value = "lila";
-    /// let _row_id = db.add_row(vec![Entry::new_string(name, value)]);
-    /// assert_eq!(db.find_first_string(name), Some(value.to_string()));
-    /// ```
-    pub fn find_first_string(&self, name: &str) -> Option<String> {
-        if let Some(row_id) = self.find_first_row_id_by_name(name) {
-            if let Some(entries) = self.by_row_id.get(&row_id) {
-                if let Some(entry) = Entry::get_first_by_name(entries, name) {
-                    if let Data::DbString(value) = entry.value {
-                        return Some(value);
-                    }
+struct SavedFileVisitor;
+
+impl<'de> serde::de::Visitor<'de> for SavedFileVisitor {
+    type Value = SavedFile;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of rows")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<SavedFile, A::Error> {
+        let mut saved = SavedDb {
+            rows: HashMap::new(),
+            row_max: RowId(0),
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "rows" => saved
+                    .rows
+                    .extend(map.next_value::<HashMap<RowId, Vec<Entry>>>()?),
+                "row_max" => saved.row_max = map.next_value()?,
+                _ => {
+                    let row_id = key.parse().map_err(serde::de::Error::custom)?;
+                    saved.rows.insert(RowId(row_id), map.next_value()?);
                 }
             }
         }
-        None
+        Ok(SavedFile(saved))
     }
+}
 
-    /// Parse `&str` into a `DbDateTime`. The format string is `%Y-%m-%d %H:%M:%S`.
-    pub fn db_datetime(v: &str) -> Result<Data, Box<Error>> {
-        let fmt = "%Y-%m-%d %H:%M:%S";
-        let r = NaiveDateTime::parse_from_str(v, fmt)?;
-        Ok(Data::DbDateTime(r))
+/// Rows returned by a projection like `Db::entries_from_row_ids()` or `Db::select()`, with
+/// lookups by entry name.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::{Db, Entry, QueryResult};
+/// let mut db = Db::new("test-db");
+/// let row_id = db.add_row(vec![
+///     Entry::new_string("word", "coche"),
+///     Entry::new_string("translation", "car"),
+///     Entry::new_string("translation", "automobile"),
+/// ]);
+/// let result = QueryResult::from(db.entries_from_row_ids(&[row_id], &["word", "translation"]));
+/// assert_eq!(result.get(0, "word"), Some(&Db::db_string("coche")));
+/// assert_eq!(result.get_all(0, "translation").len(), 2);
+/// ```
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct QueryResult {
+    rows: Vec<Vec<Entry>>,
+}
+
+impl QueryResult {
+    pub fn new(rows: Vec<Vec<Entry>>) -> QueryResult {
+        QueryResult { rows }
     }
 
-    fn add_name(&mut self, name: String, row_id: RowId) {
-        let row_ids = self.by_name.entry(name).or_insert_with(HashSet::new);
-        row_ids.insert(row_id);
+    /// Number of rows
+    pub fn len(&self) -> usize {
+        self.rows.len()
     }
 
-    fn add_value(&mut self, value: Entry, row_id: RowId) {
-        let row_ids = self.by_value.entry(value).or_insert_with(HashSet::new);
-        row_ids.insert(row_id);
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
     }
 
-    /// Add a new row with one i32
-    pub fn add_i32(&mut self, name: &str, value: i32) -> RowId {
-        self.add_row(vec![Entry::new_i32(name, value)])
+    /// Value of the first entry named `name` in row `row_index`
+    pub fn get(&self, row_index: usize, name: &str) -> Option<&Data> {
+        self.rows
+            .get(row_index)?
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| &entry.value)
     }
 
-    /// Add a new row with one string
-    pub fn add_string(&mut self, name: &str, value: &str) -> RowId {
-        self.add_row(vec![Entry::new_string(name, value)])
+    /// Values of all entries named `name` in row `row_index`, in order
+    pub fn get_all(&self, row_index: usize, name: &str) -> Vec<&Data> {
+        self.rows
+            .get(row_index)
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.name == name)
+            .map(|entry| &entry.value)
+            .collect()
     }
 
-    /// Add a new row with multiple entries.
-    pub fn add_row(&mut self, entries: Vec<Entry>) -> RowId {
-        let row_id = self.next();
-        for entry in &entries {
-            self.add_name(entry.name.clone(), row_id);
-            self.add_value(entry.clone(), row_id);
-        }
-        self.by_row_id.insert(row_id, entries);
-        row_id
+    /// The rows as returned by the projection
+    pub fn rows(&self) -> &[Vec<Entry>] {
+        &self.rows
     }
 
-    /// Add a single entry to an existing row. An existing entry with the same name is overwritten.
-    /// If multiple entries with the same name exist, they will be overwritten.
-    pub fn add_or_update_entry(&mut self, row_id: RowId, new_entry: Entry) {
-        self.remove_by_name(row_id, &new_entry.name);
-        self.add_row_id_entry(row_id, new_entry);
+    pub fn into_rows(self) -> Vec<Vec<Entry>> {
+        self.rows
     }
+}
 
-    /// Removes all entries with name 'name' and row 'row_id'. Does not delete the whole row and
-    /// leaves entries with other names.
-    pub fn remove_by_name(&mut self, row_id: RowId, name: &str) {
-        if let Some(entries) = self.by_row_id.get(&row_id) {
-            for entry in entries.iter() {
-                if let Some(row_ids) = self.by_name.get_mut(&entry.name) {
-                    row_ids.remove(&row_id);
-                }
-                if let Some(row_ids) = self.by_value.get_mut(&entry) {
-                    row_ids.remove(&row_id);
-                }
-            }
-        }
+impl From<Vec<Vec<Entry>>> for QueryResult {
+    fn from(rows: Vec<Vec<Entry>>) -> QueryResult {
+        QueryResult::new(rows)
+    }
+}
 
-        if let Some(entries) = self.by_row_id.get_mut(&row_id) {
-            entries.retain(|entry| entry.name != name);
-        }
+/// Iterator over the rows of a `Db`, created by `for (row_id, entries) in &db`. The order is
+/// unspecified.
+#[derive(Clone, Debug)]
+pub struct Rows<'a>(hash_map::Iter<'a, RowId, Vec<Entry>>);
 
-        if let Some(entries) = self.by_row_id.get(&row_id) {
-            for entry in entries.iter() {
-                if let Some(row_ids) = self.by_name.get_mut(&entry.name) {
-                    row_ids.insert(row_id);
-                }
-                if let Some(row_ids) = self.by_value.get_mut(&entry) {
-                    row_ids.insert(row_id);
-                }
-            }
-        }
-    }
+impl<'a> Iterator for Rows<'a> {
+    type Item = (RowId, &'a Vec<Entry>);
 
-    /// Removes all entries with row 'row_id'
-    pub fn remove_by_row_id(&mut self, row_id: RowId) {
-        if let Some(entries) = self.by_row_id.get(&row_id) {
-            for entry in entries.iter() {
-                if let Some(row_ids) = self.by_name.get_mut(&entry.name) {
-                    row_ids.remove(&row_id);
-                }
-                if let Some(row_ids) = self.by_value.get_mut(&entry) {
-                    row_ids.remove(&row_id);
-                }
-            }
-        }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(row_id, entries)| (*row_id, entries))
+    }
 
-        self.by_row_id.remove(&row_id);
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
     }
+}
 
-    /// Add a single entry to an existing row. Does not check if entry exists.
-    pub fn add_row_id_entry(&mut self, row_id: RowId, entry: Entry) {
-        self.by_row_id
-            .entry(row_id)
-            .or_insert_with(Vec::new)
-            .push(entry.clone());
-        self.by_name
-            .entry(entry.name.clone())
-            .or_insert_with(HashSet::new)
-            .insert(row_id);
-        self.by_value
-            .entry(entry)
-            .or_insert_with(HashSet::new)
-            .insert(row_id);
+/// # Examples
+///
+/// ```
+/// use vdb::{Db, Entry};
+/// let mut db = Db::new("test-db");
+/// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+/// let _row_id = db.add_row(vec![Entry::new_string("word", "moto")]);
+/// let mut rows = 0;
+/// for (_row_id, entries) in &db {
+///     assert_eq!(entries[0].name, "word");
+///     rows += 1;
+/// }
+/// assert_eq!(rows, 2);
+/// ```
+impl<'a> IntoIterator for &'a Db {
+    type Item = (RowId, &'a Vec<Entry>);
+    type IntoIter = Rows<'a>;
+
+    fn into_iter(self) -> Rows<'a> {
+        Rows(self.by_row_id.iter())
     }
+}
 
-    /// Delete rows in the database
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use vdb::{Db, Entry};
-    /// let mut db = Db::new("test-db");
-    /// let row_1 = db.add_row(vec![
-    ///         Entry::new_string("word", "cocina"),
-    ///         Entry::new_string("translation", "cuisine"),
-    ///         Entry::new_string("translation", "kitchen"),
-    /// ]);
-    /// let row_2 = db.add_row(vec![
-    ///         Entry::new_string("word", "coche"),
-    ///         Entry::new_string("translation", "car"),
-    /// ]);
-    /// let coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
-    /// assert_eq!(coche, Some(row_2));
-    /// db.delete_rows(&[row_1, row_2]);
-    /// let no_coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
-    /// assert_eq!(no_coche, None);
-    /// ```
-    pub fn delete_rows(&mut self, row_ids: &[RowId]) {
-        for row_id in row_ids {
-            self.remove_by_row_id(*row_id);
-        }
+/// A change reported to the callback registered with `Db::on_change()`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ChangeEvent {
+    RowAdded(RowId),
+    RowDeleted(RowId),
+    /// Entries with this name were added or changed in the row
+    EntryUpdated(RowId, String),
+}
+
+/// Holds the callback of `Db::on_change()`. Callbacks are not cloned or saved.
+///
+/// The callback must be `Send` so a `Db` can still be moved into a `ConcurrentDb` and shared
+/// between threads. It is only called through `&mut Db`, so the `Mutex` is never contended; it
+/// just keeps `Db` `Sync` without requiring `Sync` callbacks.
+#[derive(Default)]
+struct ChangeListener(Option<Mutex<ChangeCallback>>);
+
+type ChangeCallback = Box<dyn FnMut(ChangeEvent) + Send>;
+
+impl Clone for ChangeListener {
+    fn clone(&self) -> ChangeListener {
+        ChangeListener(None)
     }
+}
 
-    /// Delete all entries with this name in the whole database.
-    /// Does not delete all rows. Deletes matching entries in the row. The row will be kept if
-    /// there are entries left, otherwise deleted.
-    pub fn delete_entry_all(&mut self, name: &str) {
-        let row_ids = self.find_row_ids_by_name(name);
-        for row_id in row_ids {
-            self.remove_by_name(row_id, name);
+impl fmt::Debug for ChangeListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "ChangeListener(Some(..))"),
+            None => write!(f, "ChangeListener(None)"),
         }
     }
+}
 
-    /// Return row_ids of entries where an entry with name "name" exists.
-    pub fn find_row_ids_by_name(&self, name: &str) -> Vec<RowId> {
-        if let Some(rows) = self.by_name.get(name) {
-            rows.iter().cloned().collect::<Vec<RowId>>()
-        } else {
-            vec![]
-        }
+/// Append-only log of changed rows written after `Db::enable_wal()`. A clone does not write to
+/// the log.
+#[derive(Default, Debug)]
+struct Wal {
+    file: Option<File>,
+    /// Set when a write failed. The log misses a change from then on, so logging stops until the
+    /// next save truncates it.
+    failed: bool,
+    /// The error of the failed write, until `Db::check_wal()` returns it
+    error: Option<DbError>,
+}
+
+impl Clone for Wal {
+    fn clone(&self) -> Wal {
+        Wal::default()
     }
+}
 
-    /// Return row_ids of entries that are exactly "value". For partial string matches, use
-    /// Predicates.
-    pub fn find_row_ids_by_value(&self, name: &str, value: &Data) -> Vec<RowId> {
-        let entry = Entry {
-            name: name.to_string(),
-            value: value.clone(),
+impl Wal {
+    fn append(&mut self, record: &WalRecord) -> Result<(), DbError> {
+        let file = match &mut self.file {
+            Some(file) if !self.failed => file,
+            _ => return Ok(()),
         };
-        if let Some(rows) = self.by_value.get(&entry) {
-            rows.iter().cloned().collect::<Vec<RowId>>()
-        } else {
-            vec![]
+        let written = serde_json::to_string(record)
+            .map_err(DbError::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                Ok(file.write_all(line.as_bytes())?)
+            });
+        if written.is_err() {
+            self.failed = true;
         }
+        written
     }
 
-    /// Return reference to first entry found in a given row.
-    pub fn find_first_row_id_by_name(&self, name: &str) -> Option<RowId> {
-        if let Some(rows) = self.by_name.get(name) {
-            rows.iter().cloned().next()
-        } else {
-            None
+    /// Like `append()`, but keeps the error of a failed write for `Db::check_wal()`. Used by
+    /// changes that can not return an error themselves.
+    fn log(&mut self, record: &WalRecord) {
+        if let Err(e) = self.append(record) {
+            self.error.get_or_insert(e);
         }
     }
 
-    /// Return reference to first entry found in a given row.
-    pub fn find_first_row_id_by_value(&self, name: &str, value: &Data) -> Option<RowId> {
-        let entry = Entry {
-            name: name.to_string(),
-            value: value.clone(),
-        };
-        if let Some(rows) = self.by_value.get(&entry) {
-            rows.iter().cloned().next()
-        } else {
-            None
+    /// Empty the log after the database was saved, everything in it is in the file now.
+    fn truncate(&mut self) -> Result<(), DbError> {
+        if let Some(file) = &mut self.file {
+            file.set_len(0)?;
+            self.failed = false;
+            self.error = None;
         }
+        Ok(())
     }
+}
 
-    /// Return reference to first entry found in a given row.
-    pub fn find_first_entry_by_name(&self, row_id: RowId, name: &str) -> Option<Entry> {
-        Entry::get_first_by_name(&self.by_row_id[&row_id], name)
+/// One line of the log written after `Db::enable_wal()`
+#[derive(Serialize, Deserialize)]
+enum WalRecord<'a> {
+    /// The row was added or changed and now holds these entries
+    Row {
+        row_id: RowId,
+        entries: Cow<'a, [Entry]>,
+    },
+    Deleted(RowId),
+    Cleared,
+    RowMax(RowId),
+}
+
+/// Lowercase `value` and strip diacritics by removing combining marks after canonical
+/// decomposition.
+#[cfg(feature = "unicode-normalization")]
+fn fold(value: &str) -> String {
+    value
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+fn fold(value: &str) -> String {
+    value.to_lowercase()
+}
+
+/// Result of `Db::add_or_update_entry()`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Upsert {
+    /// No entry with the same name existed, the entry was added
+    Inserted,
+    /// The given number of entries with the same name were replaced
+    Updated(usize),
+}
+
+/// Result of `Db::diff()`. All lists are sorted by RowId.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct DbDiff {
+    /// Rows that only exist in the database `diff()` was called on
+    pub only_in_self: Vec<RowId>,
+    /// Rows that only exist in the other database
+    pub only_in_other: Vec<RowId>,
+    /// Rows that exist in both databases but with different entries
+    pub changed: Vec<RowId>,
+}
+
+impl DbDiff {
+    /// Returns true if both databases hold the same rows
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
     }
+}
 
-    pub fn find_by_predicate(&self, predicate: &Predicate) -> Vec<RowId> {
-        if predicate.predicate_type == PredicateType::Equal {
-            if let Some(row_ids) = self.by_value.get(&predicate.entry) {
-                row_ids.iter().cloned().collect::<Vec<RowId>>()
-            } else {
-                vec![]
-            }
-        } else {
-            self.by_row_id
-                .iter()
-                .filter(|(_row_id, entries)| Entry::compare_all(entries, predicate))
-                .map(|(row_id, _entries)| *row_id)
-                .collect::<Vec<RowId>>()
+/// Container for the database. Usually only one is used per application.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::{Db, Entry};
+/// let mut db = Db::new("test-db");
+/// let _row_id = db.add_row(vec![Entry::new_string("mundo", "world")]);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Db {
+    full_filename: String,
+    row_max: RowId,
+    by_row_id: HashMap<RowId, Vec<Entry>>,
+    by_name: HashMap<String, HashSet<RowId>>,
+    by_value: HashMap<Entry, HashSet<RowId>>,
+    #[serde(skip)]
+    prefix_index: Option<BTreeMap<(String, String), HashSet<RowId>>>,
+    #[serde(skip)]
+    on_change: ChangeListener,
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    timestamps: bool,
+    #[serde(skip)]
+    trim_strings: bool,
+    #[serde(skip)]
+    wal: Wal,
+}
+
+/// Databases are equal if they have the same rows and `row_max`. The indexes are derived from
+/// the rows and not compared, nor are the name, the change callback, the change log, whether
+/// there are unsaved changes and the `enable_timestamps()` and `enable_trim()` settings.
+impl PartialEq for Db {
+    fn eq(&self, other: &Db) -> bool {
+        self.row_max == other.row_max && self.by_row_id == other.by_row_id
+    }
+}
+
+impl Db {
+    /// Create new database in memory. The file is not created until `save()` is called.
+    pub fn new(filename: &str) -> Db {
+        Db {
+            full_filename: Db::build_filename(filename),
+            row_max: RowId(0),
+            by_row_id: HashMap::new(),
+            by_name: HashMap::new(),
+            by_value: HashMap::new(),
+            prefix_index: None,
+            on_change: ChangeListener::default(),
+            dirty: false,
+            timestamps: false,
+            trim_strings: false,
+            wal: Wal::default(),
         }
     }
 
-    /// Returns all rows if no predicates are given.
-    /// The first predicate is evaluated first and should have high selectivity, i. e. evaluate to a
-    /// small number of rows, to improve execution time. The number of results can be limited with
-    /// `Some(max_results)`
+    /// Create a new database in memory holding `rows`, which get the RowIds `1..=rows.len()`.
     ///
     /// # Examples
     ///
     /// ```
-    /// // Like SQL "select name, value from testdb where name='coche' limit 15"
-    /// use vdb::{Data, Db, Entry, Predicate, RowId};
-    /// let mut db = Db::new("test-db");
-    /// let _id = db.add_row(vec![
-    ///     Entry {
-    ///         name: String::from("set"),
-    ///         value: Db::db_string("es-en"),
-    ///     },
-    ///     Entry {
-    ///         name: String::from("name"),
-    ///         value: Db::db_string("coche"),
-    ///     },
-    ///     Entry {
-    ///         name: String::from("value"),
-    ///         value: Db::db_string("car"),
-    ///     },
-    /// ]);
-    /// let predicates = vec![Predicate::new_equal_string("name", "coche")];
-    /// let row_ids = db.find_row_ids_by_predicate(&predicates, Some(15));
-    /// assert_eq!(row_ids, [RowId(1)]);
-    /// assert_eq!(db.entries_from_row_ids(&row_ids, &["name", "value"])[0][0], Entry::new_string("name", "coche"));
+    /// use vdb::{Db, Entry, RowId};
+    /// let db = Db::from_rows(
+    ///     "test-db",
+    ///     vec![
+    ///         vec![Entry::new_string("word", "coche")],
+    ///         vec![Entry::new_string("word", "moto")],
+    ///     ],
+    /// );
+    /// assert_eq!(db.get_string(RowId(2), "word"), Some(String::from("moto")));
     /// ```
-    /// See also find_entries_by_predicate()
-    pub fn find_row_ids_by_predicate(
-        &self,
-        predicates: &[Predicate],
-        max_results: Option<usize>,
-    ) -> Vec<RowId> {
-        let max_results = if let Some(max_results) = max_results {
-            max_results
-        } else {
-            self.by_row_id.len()
+    pub fn from_rows(filename: &str, rows: Vec<Vec<Entry>>) -> Db {
+        let mut db = Db::new(filename);
+        db.add_rows(rows);
+        db
+    }
+
+    /// Load a database file from the filesystem under the subdirectory `save/`. Rows keep the
+    /// RowIds they were saved with. Files written by `save_compressed()` are detected and
+    /// decompressed if the `flate2` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the file can not be read and `DbError::Serde` if its contents can
+    /// not be parsed.
+    pub fn load(filename: &str) -> Result<Db, DbError> {
+        let full_filename = Db::build_filename(filename);
+        let mut file = File::open(full_filename)?;
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+        Db::from_contents(filename, contents)
+    }
+
+    /// Like `load()`, but returns a new empty database if the file does not exist or is empty.
+    /// This is the usual way to open a database that may not have been saved yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let db = Db::load_or_new("test-db-never-saved").unwrap();
+    /// assert_eq!(db.row_count(), 0);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if an existing file can not be read and `DbError::Serde` if its
+    /// contents can not be parsed.
+    pub fn load_or_new(filename: &str) -> Result<Db, DbError> {
+        let full_filename = Db::build_filename(filename);
+        let mut file = match File::open(full_filename) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Db::new(filename)),
+            Err(e) => return Err(e.into()),
         };
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+        if contents.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(Db::new(filename));
+        }
+        Db::from_contents(filename, contents)
+    }
 
-        if predicates.is_empty() {
-            self.find_all_row_ids()
-        } else {
-            let predicate0 = &predicates[0];
-            let mut row_ids = self.find_by_predicate(predicate0);
+    /// Like `load()`, but returns a read-only `SharedDb` for data that is never changed, like a
+    /// static vocabulary. Only the `&self` methods of `Db` are available, so mutating the
+    /// database is a compile error:
+    ///
+    /// ```compile_fail
+    /// use vdb::{Db, Entry};
+    /// let db = Db::load_readonly("test-db").unwrap();
+    /// db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as `load()`.
+    pub fn load_readonly(filename: &str) -> Result<SharedDb, DbError> {
+        Ok(Db::load(filename)?.into_shared())
+    }
 
-            for predicate in &predicates[1..] {
-                let new_row_ids = row_ids
-                    .iter()
-                    .filter(|&row_id| self.match_row(*row_id, predicate))
-                    .cloned()
-                    .collect::<Vec<RowId>>();
-                row_ids = new_row_ids;
-            }
-            if max_results < row_ids.len() {
-                let _ = row_ids.drain(max_results..).collect::<Vec<RowId>>();
-            }
-            row_ids.sort();
-            row_ids.dedup();
-            row_ids
+    /// Parse the contents of a database file, compressed or not.
+    fn from_contents(filename: &str, mut contents: Vec<u8>) -> Result<Db, DbError> {
+        if contents.starts_with(&GZIP_MAGIC) {
+            contents = decompress(&contents)?;
         }
+        let saved: SavedFile = serde_json::from_slice(&contents)?;
+        Ok(Db::from_saved(filename, saved.0))
     }
 
-    /// Returns all rows in the database
-    pub fn find_all_row_ids(&self) -> Vec<RowId> {
-        self.by_row_id.keys().cloned().collect::<Vec<RowId>>()
+    /// Like `load()`, but parses the file while reading it instead of reading it into memory
+    /// first. This lowers peak memory usage for large databases. Compressed files are
+    /// decompressed while reading if the `flate2` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the file can not be read and `DbError::Serde` if its contents can
+    /// not be parsed.
+    pub fn load_streaming(filename: &str) -> Result<Db, DbError> {
+        let full_filename = Db::build_filename(filename);
+        let mut reader = io::BufReader::new(File::open(full_filename)?);
+        let saved: SavedFile = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            serde_json::from_reader(decompress_reader(reader)?)?
+        } else {
+            serde_json::from_reader(reader)?
+        };
+        Ok(Db::from_saved(filename, saved.0))
     }
 
-    #[cfg(test)]
-    pub fn find_entries_by_predicate(
-        &self,
-        predicates: &[Predicate],
-        entries: &[&str],
-    ) -> Vec<Vec<Entry>> {
-        let row_ids = self.find_row_ids_by_predicate(predicates, None);
-        self.entries_from_row_ids(&row_ids, entries)
+    /// Like `from_row_id_map()`, but keeps the saved `row_max` if it is higher than the highest
+    /// RowId.
+    fn from_saved(filename: &str, saved: SavedDb<HashMap<RowId, Vec<Entry>>>) -> Db {
+        let mut db = Db::from_row_id_map(filename, saved.rows);
+        db.row_max = db.row_max.max(saved.row_max);
+        db
     }
 
-    /// Returns entries for given row_ids.
-    pub fn entries_from_row_ids(&self, row_ids: &[RowId], names: &[&str]) -> Vec<Vec<Entry>> {
-        let names = names.iter().map(|s| s.to_string()).collect::<Vec<String>>();
-        let mut result: Vec<Vec<Entry>> = vec![];
-        for row_id in row_ids {
-            let entries = &self.by_row_id[&row_id];
+    fn from_row_id_map(filename: &str, row_id_map: HashMap<RowId, Vec<Entry>>) -> Db {
+        let mut db = Db::new(filename);
+        for (row_id, entries) in row_id_map {
+            db.insert_row(row_id, entries);
+        }
+        db.dirty = false;
+        db
+    }
 
-            let mut ordered: Vec<Entry> = vec![];
-            for name in &names {
-                for entry in entries.iter().filter(|entry| &entry.name == name) {
-                    ordered.push(entry.clone());
-                }
-            }
+    /// Save database under the subdirectory `save/` with the same name it was `open`ed or `create`d
+    /// with. The subdirectory `save/` must exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Serde` if serialization fails and `DbError::Io` if the file can not be
+    /// written. The file is left untouched if serialization fails.
+    pub fn save(&mut self) -> Result<(), DbError> {
+        let serialized = self.serialize()?;
+        let path = Path::new(&self.full_filename);
+        let mut file = File::create(path)?;
+        file.write_all(serialized.as_bytes())?;
+        self.saved()
+    }
 
-            result.push(ordered);
+    /// Like `save()`, but only writes the file if the database was changed since it was
+    /// created, loaded or last saved. Returns `true` if the file was written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::new("test-db");
+    /// assert!(!db.save_if_dirty().unwrap());
+    /// db.add_string("word", "coche");
+    /// assert!(db.save_if_dirty().unwrap());
+    /// assert!(!db.save_if_dirty().unwrap());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as `save()`.
+    pub fn save_if_dirty(&mut self) -> Result<bool, DbError> {
+        if !self.dirty {
+            return Ok(false);
         }
-        result
+        self.save()?;
+        Ok(true)
     }
 
-    /// Check if a predicate is true for a given row_id.
-    fn match_row(&self, row_id: RowId, predicate: &Predicate) -> bool {
-        let entries = &self.by_row_id[&row_id];
-        Entry::compare_all(&entries, predicate)
+    /// Returns `true` if the database has changes that were not saved.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
     }
 
-    fn next(&mut self) -> RowId {
-        self.row_max.0 += 1;
-        self.row_max
+    /// Called after the whole database was written to its file.
+    fn saved(&mut self) -> Result<(), DbError> {
+        self.dirty = false;
+        self.wal.truncate()
     }
 
-    fn build_filename(name: &str) -> String {
-        format!("save/{}", name)
+    /// Log every change to the file `filename` under `save/`, one JSON line per changed row, so
+    /// changes made after the last save survive a crash. Every save empties the log. If the
+    /// database has unsaved changes, a snapshot of all rows is logged first. Restore the database
+    /// with `replay_wal()`. The file is created if necessary and appended to otherwise.
+    ///
+    /// Changes can not return an error when writing to the log fails, `check_wal()` reports it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::load_or_new("test-db-wal").unwrap();
+    /// db.enable_wal("test-db-wal.wal").unwrap();
+    /// let row_id = db.add_string("word", "coche");
+    /// db.check_wal().unwrap();
+    /// // No save, e.g. after a crash
+    /// let restored = Db::replay_wal("test-db-wal", "test-db-wal.wal").unwrap();
+    /// assert_eq!(restored.get_string(row_id, "word"), Some(String::from("coche")));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the file can not be opened or the snapshot can not be written.
+    /// The log is not enabled then.
+    pub fn enable_wal(&mut self, filename: &str) -> Result<(), DbError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Db::build_filename(filename))?;
+        let mut wal = Wal {
+            file: Some(file),
+            failed: false,
+            error: None,
+        };
+        if self.dirty {
+            wal.append(&WalRecord::Cleared)?;
+            for (row_id, entries) in &self.by_row_id {
+                wal.append(&WalRecord::Row {
+                    row_id: *row_id,
+                    entries: Cow::Borrowed(entries),
+                })?;
+            }
+            wal.append(&WalRecord::RowMax(self.row_max))?;
+        }
+        self.wal = wal;
+        Ok(())
     }
 
-    #[cfg(test)]
-    pub fn debug_rows(&self, row_ids: &[RowId]) -> Vec<Vec<Entry>> {
-        let mut result: Vec<Vec<Entry>> = vec![];
-        for row_id in row_ids {
-            let entries = &self.by_row_id[&row_id];
-            result.push(entries.clone());
+    /// Returns the error of the first write to the change log that failed since `enable_wal()` or
+    /// the last save. The log misses a change from then on, so logging stops until the next
+    /// save, which empties the log. Check this after changes that must survive a crash and save
+    /// if it fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if writing to the log failed and `DbError::Serde` if a change could
+    /// not be serialized. The error is returned once.
+    pub fn check_wal(&mut self) -> Result<(), DbError> {
+        match self.wal.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
-        result
     }
-}
 
-mod tests {
-    #[cfg(test)]
-    use super::{Data, Db, Entry, Predicate, RowId};
-    #[cfg(test)]
-    use chrono::NaiveDateTime;
+    /// Load the database `filename` like `load_or_new()` and apply the changes logged to the file
+    /// `wal_filename` under `save/` since it was last saved. A missing log counts as empty. An
+    /// incomplete last line, as left by a crash while writing, is ignored. The log is not enabled
+    /// on the returned database.
+    ///
+    /// # Errors
+    ///
+    /// Same as `load()`. Returns `DbError::Jsonl` if a line of the log can not be parsed.
+    pub fn replay_wal(filename: &str, wal_filename: &str) -> Result<Db, DbError> {
+        let mut db = Db::load_or_new(filename)?;
+        let contents = match fs::read_to_string(Db::build_filename(wal_filename)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(db),
+            Err(e) => return Err(e.into()),
+        };
+        let complete = contents.ends_with('\n');
+        let lines = contents.lines().collect::<Vec<&str>>();
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = match serde_json::from_str::<WalRecord>(line) {
+                Ok(record) => record,
+                Err(_) if i + 1 == lines.len() && !complete => break,
+                Err(error) => return Err(DbError::Jsonl { line: i + 1, error }),
+            };
+            match record {
+                WalRecord::Row { row_id, entries } => {
+                    db.remove_by_row_id(row_id);
+                    db.insert_row(row_id, entries.into_owned());
+                }
+                WalRecord::Deleted(row_id) => db.remove_by_row_id(row_id),
+                WalRecord::Cleared => db.clear(),
+                WalRecord::RowMax(row_max) => db.row_max = row_max,
+            }
+        }
+        Ok(db)
+    }
 
-    #[test]
-    fn match_row() {
-        let db = new_db_with_entries("testdb");
+    /// Write the current state of row `row_id` to the change log, if enabled.
+    fn log_row(&mut self, row_id: RowId) {
+        if self.wal.file.is_none() {
+            return;
+        }
+        let record = match self.by_row_id.get(&row_id) {
+            Some(entries) => WalRecord::Row {
+                row_id,
+                entries: Cow::Borrowed(entries),
+            },
+            None => WalRecord::Deleted(row_id),
+        };
+        self.wal.log(&record);
+    }
 
-        let p1 = Predicate::new_equal_string("name", "coche");
-        let p2 = Predicate::new_starts_with("name", "co");
-        let p3 = Predicate::new_contains("name", "och");
+    /// Save the rows of this database under `namespace` in the file `filename` under `save/`,
+    /// which holds any number of namespaced databases in one JSON document. Other namespaces in
+    /// the file are kept, an existing `namespace` is replaced. The file is created if it does not
+    /// exist. Unlike `save()`, this does not reset `is_dirty()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut vocabulary = Db::new("vocabulary");
+    /// let row_id = vocabulary.add_string("word", "coche");
+    /// vocabulary.save_namespaced("vocabulary", "test-db-namespaced").unwrap();
+    /// let loaded = Db::load_namespaced("vocabulary", "test-db-namespaced").unwrap();
+    /// assert_eq!(loaded.get_string(row_id, "word"), Some(String::from("coche")));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the file can not be read or written and `DbError::Serde` if an
+    /// existing file is not a namespaced document.
+    pub fn save_namespaced(&self, namespace: &str, filename: &str) -> Result<(), DbError> {
+        let full_filename = Db::build_filename(filename);
+        let mut namespaces = Db::read_namespaces(&full_filename)?;
+        let rows = self
+            .by_row_id
+            .iter()
+            .filter(|(_row_id, entries)| !entries.is_empty())
+            .collect::<HashMap<&RowId, &Vec<Entry>>>();
+        let saved = SavedDb {
+            rows,
+            row_max: self.row_max,
+        };
+        namespaces.insert(namespace.to_string(), serde_json::to_value(saved)?);
+        fs::write(full_filename, serde_json::to_string_pretty(&namespaces)?)?;
+        Ok(())
+    }
 
-        println!("{:?}", db.debug_rows(&vec![RowId(2)]));
-        println!("{:?}", db.debug_rows(&vec![RowId(1)]));
+    /// Load the database stored under `namespace` by `save_namespaced()` in the file `filename`
+    /// under `save/`.
+    /// Returns an empty database if the file or the namespace does not exist. The database is
+    /// named after the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the file can not be read and `DbError::Serde` if it can not be
+    /// parsed.
+    pub fn load_namespaced(namespace: &str, filename: &str) -> Result<Db, DbError> {
+        let full_filename = Db::build_filename(filename);
+        let saved = match Db::read_namespaces(&full_filename)?.remove(namespace) {
+            Some(saved) => serde_json::from_value::<SavedFile>(saved)?.0,
+            None => SavedDb {
+                rows: HashMap::new(),
+                row_max: RowId(0),
+            },
+        };
+        Ok(Db::from_saved(namespace, saved))
+    }
 
-        assert_eq!(db.match_row(RowId(2), &p1), true);
-        assert_eq!(db.match_row(RowId(2), &p2), true);
-        assert_eq!(db.match_row(RowId(2), &p3), true);
+    /// Read the namespaces of a file written by `save_namespaced()`. Namespaces stay unparsed
+    /// JSON until they are used.
+    fn read_namespaces(
+        full_filename: &str,
+    ) -> Result<BTreeMap<String, serde_json::Value>, DbError> {
+        match fs::read(full_filename) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        assert_eq!(db.match_row(RowId(1), &p1), false);
-        assert_eq!(db.match_row(RowId(1), &p2), false);
-        assert_eq!(db.match_row(RowId(1), &p3), false);
+    /// Save only the rows `row_ids` as a new database `filename` under `save/`. The rows are
+    /// renumbered from 1 in the order of `row_ids`; missing RowIds are skipped. This database is
+    /// not changed.
+    ///
+    /// # Errors
+    ///
+    /// Same as `save()`.
+    pub fn save_subset(&self, row_ids: &[RowId], filename: &str) -> Result<(), DbError> {
+        let rows = row_ids
+            .iter()
+            .filter_map(|row_id| self.by_row_id.get(row_id).cloned())
+            .collect::<Vec<Vec<Entry>>>();
+        Db::from_rows(filename, rows).save()
     }
 
-    #[test]
-    fn starts_with_contains() {
-        let s1 = Db::db_string("hello");
-        let s2 = Db::db_string("hello world");
-        let s3 = Db::db_string("o wor");
-        assert!(s2.starts_with(&s1));
-        assert_eq!(s1.starts_with(&s2), false);
-        assert!(s2.contains(&s3));
-        assert_eq!(s3.contains(&s2), false);
+    /// Like `save()`, but first renames an existing file to `<name>.bak`, replacing an older
+    /// backup. This keeps the previous version around after a bad edit.
+    ///
+    /// # Errors
+    ///
+    /// Same as `save()`. Nothing is renamed if serialization fails.
+    pub fn save_with_backup(&mut self) -> Result<(), DbError> {
+        let serialized = self.serialize()?;
+        let path = Path::new(&self.full_filename);
+        if path.exists() {
+            let backup = format!("{}.bak", self.full_filename);
+            if Path::new(&backup).exists() {
+                fs::remove_file(&backup)?;
+            }
+            fs::rename(path, &backup)?;
+        }
+        let mut file = File::create(path)?;
+        file.write_all(serialized.as_bytes())?;
+        self.saved()
     }
 
-    #[test]
-    fn compare() {
-        let p1 = Predicate::new_equal_string("set", "es-en");
-        let p2 = Predicate::new_starts_with("set", "es");
-        let p3 = Predicate::new_contains("set", "s-e");
+    /// Like `save()`, but compresses the file with gzip. `load()` detects compressed files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db-compressed");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// db.save_compressed().unwrap();
+    /// assert_eq!(Db::load_compressed("test-db-compressed").unwrap(), db);
+    /// ```
+    #[cfg(feature = "flate2")]
+    pub fn save_compressed(&mut self) -> Result<(), DbError> {
+        let serialized = self.serialize()?;
+        let path = Path::new(&self.full_filename);
+        let file = File::create(path)?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(serialized.as_bytes())?;
+        encoder.finish()?;
+        self.saved()
+    }
 
-        let e1 = Entry {
-            name: String::from("set"),
-            value: Db::db_string("es-en"),
+    /// Load a database saved with `save_compressed()`. Same as `load()`, which handles both
+    /// formats.
+    #[cfg(feature = "flate2")]
+    pub fn load_compressed(filename: &str) -> Result<Db, DbError> {
+        Db::load(filename)
+    }
+
+    /// Save the database in the compact binary bincode format instead of JSON. Load it with
+    /// `load_binary()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db-binary");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// db.save_binary().unwrap();
+    /// assert_eq!(Db::load_binary("test-db-binary").unwrap(), db);
+    /// ```
+    #[cfg(feature = "bincode")]
+    pub fn save_binary(&mut self) -> Result<(), DbError> {
+        self.by_row_id.retain(|_key, value| !value.is_empty());
+        let path = Path::new(&self.full_filename);
+        let file = io::BufWriter::new(File::create(path)?);
+        let saved = SavedDb {
+            rows: &self.by_row_id,
+            row_max: self.row_max,
         };
+        bincode::serialize_into(file, &saved)?;
+        self.saved()
+    }
 
-        let e2 = Entry {
-            name: String::from("set"),
-            value: Db::db_string("en-es"),
+    /// Load a database file written by `save_binary()`. Rows keep their RowIds.
+    #[cfg(feature = "bincode")]
+    pub fn load_binary(filename: &str) -> Result<Db, DbError> {
+        let full_filename = Db::build_filename(filename);
+        let contents = fs::read(full_filename)?;
+        // Older files only hold the rows, which leaves no bytes for `row_max`.
+        let saved = match bincode::deserialize(&contents) {
+            Ok(saved) => saved,
+            Err(_) => SavedDb {
+                rows: bincode::deserialize(&contents)?,
+                row_max: RowId(0),
+            },
         };
+        Ok(Db::from_saved(filename, saved))
+    }
 
-        assert!(e1.compare(&p1));
-        assert_eq!(e2.compare(&p1), false);
+    fn serialize(&mut self) -> Result<String, DbError> {
+        self.by_row_id.retain(|_key, value| !value.is_empty());
+        Ok(serde_json::to_string_pretty(&SavedDb {
+            rows: &self.by_row_id,
+            row_max: self.row_max,
+        })?)
+    }
 
-        assert!(e1.compare(&p2));
-        assert_eq!(e2.compare(&p2), false);
+    /// Import rows from CSV data. Every record becomes a new row with one `DbString` entry per
+    /// field. With `has_header` the first record supplies the entry names, otherwise the names
+    /// are `col0`, `col1`, ... Fields beyond the header are named by position as well. Blank lines
+    /// are skipped. Returns the RowIds of the new rows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let csv = "word,translation\ncoche,car\n";
+    /// let row_ids = db.import_csv(csv.as_bytes(), true).unwrap();
+    /// let entries = db.entries_from_row_ids(&row_ids, &["word", "translation"]);
+    /// assert_eq!(entries[0][1], Entry::new_string("translation", "car"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the reader fails and `DbError::Csv` if a quoted field is not
+    /// terminated.
+    pub fn import_csv(
+        &mut self,
+        mut reader: impl Read,
+        has_header: bool,
+    ) -> Result<Vec<RowId>, DbError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        let mut records = parse_csv(&contents)?.into_iter();
+        let header = if has_header {
+            records.next().unwrap_or_default()
+        } else {
+            vec![]
+        };
 
-        assert!(e1.compare(&p3));
-        assert_eq!(e2.compare(&p3), false);
+        let mut row_ids = vec![];
+        for record in records {
+            let entries = record
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match header.get(i) {
+                    Some(name) => Entry::new_string(name, value),
+                    None => Entry::new_string(&format!("col{}", i), value),
+                })
+                .collect::<Vec<Entry>>();
+            row_ids.push(self.add_row(entries));
+        }
+        Ok(row_ids)
     }
 
-    #[cfg(test)]
-    fn new_db_with_entries(name: &str) -> Db {
-        let mut db = Db::new(name);
-        let _id = db.add_row(vec![
-            Entry {
-                name: String::from("set"),
-                value: Db::db_string("es-en"),
-            },
-            Entry {
-                name: String::from("name"),
-                value: Db::db_string("disfrutar"),
-            },
-            Entry {
-                name: String::from("value"),
-                value: Db::db_string("to enjoy"),
-            },
-        ]);
+    /// Write the database as JSON Lines: one `{"row_id":N,"entries":[...]}` object per row and
+    /// line, sorted by RowId. Rows are written one at a time, the output is not built in memory.
+    /// Empty rows are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::new("test-db");
+    /// db.add_i32("add_counter", 1);
+    /// let mut out = vec![];
+    /// db.export_jsonl(&mut out).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "{\"row_id\":1,\"entries\":[{\"name\":\"add_counter\",\"value\":{\"DbI32\":1}}]}\n"
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Serde` if a row can not be serialized and `DbError::Io` if writing fails.
+    pub fn export_jsonl(&self, mut writer: impl Write) -> Result<(), DbError> {
+        let mut row_ids = self.find_all_row_ids();
+        row_ids.sort();
+        for row_id in row_ids {
+            let entries = &self.by_row_id[&row_id];
+            if entries.is_empty() {
+                continue;
+            }
+            let row = JsonlRow {
+                row_id,
+                entries: Cow::Borrowed(entries),
+            };
+            serde_json::to_writer(&mut writer, &row)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Read rows written by `export_jsonl()`, one row object per line, and add them as new rows.
+    /// The saved `row_id`s are not kept, the rows get new RowIds which are returned in the order
+    /// of the lines. Blank lines are skipped. Nothing is added if any line is malformed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let jsonl = r#"{"row_id":7,"entries":[{"name":"word","value":{"DbString":"coche"}}]}"#;
+    /// let row_ids = db.import_jsonl(jsonl.as_bytes()).unwrap();
+    /// assert_eq!(
+    ///     db.entries_from_row_ids(&row_ids, &["word"]),
+    ///     [[Entry::new_string("word", "coche")]]
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::Io` if the reader fails and `DbError::Jsonl` with the line number if a
+    /// line is not a valid row object.
+    pub fn import_jsonl(&mut self, reader: impl Read) -> Result<Vec<RowId>, DbError> {
+        let mut rows = vec![];
+        for (i, line) in io::BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row: JsonlRow = serde_json::from_str(&line)
+                .map_err(|error| DbError::Jsonl { line: i + 1, error })?;
+            rows.push(row.entries.into_owned());
+        }
+        Ok(self.add_rows(rows))
+    }
+
+    /// Turn the database into a read-only handle that can be cloned cheaply and shared between
+    /// threads.
+    pub fn into_shared(self) -> SharedDb {
+        SharedDb(Arc::new(self))
+    }
+
+    /// Returns the filename of the database as given to `new()` or `load()`, without the save
+    /// directory.
+    pub fn get_name(&self) -> String {
+        let path = Path::new(&self.full_filename);
+        path.strip_prefix(SAVE_DIR)
+            .ok()
+            .or_else(|| path.file_name().map(Path::new))
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Returns a new Data::DbString
+    pub fn db_string(v: &str) -> Data {
+        Data::DbString(String::from(v))
+    }
+
+    /// Returns a new Data::DbI32
+    pub fn db_i32(v: i32) -> Data {
+        Data::DbI32(v)
+    }
+
+    /// Returns a new Data::DbI64
+    pub fn db_i64(v: i64) -> Data {
+        Data::DbI64(v)
+    }
+
+    /// Returns a new Data::DbBytes
+    pub fn db_bytes(v: &[u8]) -> Data {
+        Data::DbBytes(v.to_vec())
+    }
+
+    /// Find a i32 by name
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let name = "yoyo";
+    /// let value = 7;
+    /// let _row_id = db.add_row(vec![Entry::new_i32(name, value)]);
+    /// assert_eq!(db.find_first_i32(name), Some(value));
+    /// ```
+    pub fn find_first_i32(&self, name: &str) -> Option<i32> {
+        if let Some(row_id) = self.find_first_row_id_by_name(name) {
+            if let Some(entries) = self.by_row_id.get(&row_id) {
+                if let Some(entry) = Entry::get_first_by_name_ref(entries, name) {
+                    if let Data::DbI32(value) = entry.value {
+                        return Some(value);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Find a string by name
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let name = "yoyo";
+    /// let value = "lila";
+    /// let _row_id = db.add_row(vec![Entry::new_string(name, value)]);
+    /// assert_eq!(db.find_first_string(name), Some(value.to_string()));
+    /// ```
+    pub fn find_first_string(&self, name: &str) -> Option<String> {
+        if let Some(row_id) = self.find_first_row_id_by_name(name) {
+            if let Some(entries) = self.by_row_id.get(&row_id) {
+                if let Some(entry) = Entry::get_first_by_name_ref(entries, name) {
+                    if let Data::DbString(value) = &entry.value {
+                        return Some(value.clone());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Find a datetime by name
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let name = "add_date";
+    /// let value = Db::db_datetime("2018-01-01 10:00:00").unwrap();
+    /// let _row_id = db.add_row(vec![Entry { name: name.to_string(), value }]);
+    /// assert_eq!(db.find_first_datetime(name).unwrap().to_string(), "2018-01-01 10:00:00");
+    /// ```
+    pub fn find_first_datetime(&self, name: &str) -> Option<NaiveDateTime> {
+        if let Some(row_id) = self.find_first_row_id_by_name(name) {
+            if let Some(entries) = self.by_row_id.get(&row_id) {
+                if let Some(entry) = Entry::get_first_by_name_ref(entries, name) {
+                    if let Data::DbDateTime(value) = entry.value {
+                        return Some(value);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the value of the first entry named `name` in row `row_id` if it is a `DbI32`.
+    pub fn get_i32(&self, row_id: RowId, name: &str) -> Option<i32> {
+        match self.first_value(row_id, name) {
+            Some(Data::DbI32(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the first entry named `name` in row `row_id` if it is a `DbI64` or a
+    /// `DbI32`.
+    pub fn get_i64(&self, row_id: RowId, name: &str) -> Option<i64> {
+        match self.first_value(row_id, name) {
+            Some(Data::DbI64(value)) => Some(*value),
+            Some(Data::DbI32(value)) => Some(i64::from(*value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the first entry named `name` in row `row_id` if it is a `DbString`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// assert_eq!(db.get_string(row_id, "word"), Some(String::from("coche")));
+    /// assert_eq!(db.get_i32(row_id, "word"), None);
+    /// ```
+    pub fn get_string(&self, row_id: RowId, name: &str) -> Option<String> {
+        match self.first_value(row_id, name) {
+            Some(Data::DbString(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the first entry named `name` in row `row_id` if it is a `DbDateTime`.
+    pub fn get_datetime(&self, row_id: RowId, name: &str) -> Option<NaiveDateTime> {
+        match self.first_value(row_id, name) {
+            Some(Data::DbDateTime(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of all entries named `name` in row `row_id`, in the order they are
+    /// stored. Empty if the row does not exist or has no such entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![
+    ///     Entry::new_string("word", "coche"),
+    ///     Entry::new_string("translation", "car"),
+    ///     Entry::new_string("translation", "automobile"),
+    /// ]);
+    /// let translations = db.values_by_name(row_id, "translation");
+    /// assert_eq!(translations, [Db::db_string("car"), Db::db_string("automobile")]);
+    /// ```
+    pub fn values_by_name(&self, row_id: RowId, name: &str) -> Vec<Data> {
+        self.by_row_id
+            .get(&row_id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|entry| entry.name == name)
+                    .map(|entry| entry.value.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Parse `&str` into a `DbDateTime`. The format string is `%Y-%m-%d %H:%M:%S`, the same
+    /// `Display` prints.
+    pub fn db_datetime(v: &str) -> Result<Data, DbError> {
+        let r = NaiveDateTime::parse_from_str(v, DATETIME_FORMAT)?;
+        Ok(Data::DbDateTime(r))
+    }
+
+    /// Parse `&str` into a `DbDateTime`, trying `%Y-%m-%d %H:%M:%S`, `%Y-%m-%d %H:%M`, a date
+    /// only (`%Y-%m-%d`, at midnight) and RFC 3339 in this order. For RFC 3339 the time is kept
+    /// as written and the offset is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let midnight = Db::db_datetime("2013-11-22 00:00:00").unwrap();
+    /// assert_eq!(Db::db_datetime_flexible("2013-11-22").unwrap(), midnight);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::DateTimeFormats` listing the formats tried if none matches.
+    pub fn db_datetime_flexible(v: &str) -> Result<Data, DbError> {
+        const DATETIME_FORMATS: [&str; 2] = [DATETIME_FORMAT, "%Y-%m-%d %H:%M"];
+        const DATE_FORMAT: &str = "%Y-%m-%d";
+        const RFC3339: &str = "RFC 3339";
+
+        let v = v.trim();
+        for fmt in &DATETIME_FORMATS {
+            if let Ok(dt) = NaiveDateTime::parse_from_str(v, fmt) {
+                return Ok(Data::DbDateTime(dt));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(v, DATE_FORMAT) {
+            if let Some(dt) = date.and_hms_opt(0, 0, 0) {
+                return Ok(Data::DbDateTime(dt));
+            }
+        }
+        if let Ok(dt) = DateTime::parse_from_rfc3339(v) {
+            return Ok(Data::DbDateTime(dt.naive_local()));
+        }
+        let mut tried = DATETIME_FORMATS.to_vec();
+        tried.extend_from_slice(&[DATE_FORMAT, RFC3339]);
+        Err(DbError::DateTimeFormats {
+            value: v.to_string(),
+            tried,
+        })
+    }
+
+    fn add_name(&mut self, name: String, row_id: RowId) {
+        self.dirty = true;
+        let row_ids = self.by_name.entry(name).or_default();
+        row_ids.insert(row_id);
+    }
+
+    fn add_value(&mut self, entry: Entry, row_id: RowId) {
+        self.dirty = true;
+        if let (Some(prefix_index), Data::DbString(value)) = (&mut self.prefix_index, &entry.value)
+        {
+            prefix_index
+                .entry((entry.name.clone(), value.clone()))
+                .or_default()
+                .insert(row_id);
+        }
+        let row_ids = self.by_value.entry(entry).or_default();
+        row_ids.insert(row_id);
+    }
+
+    fn remove_value(&mut self, entry: &Entry, row_id: RowId) {
+        self.dirty = true;
+        if let (Some(prefix_index), Data::DbString(value)) = (&mut self.prefix_index, &entry.value)
+        {
+            let key = (entry.name.clone(), value.clone());
+            if let Some(row_ids) = prefix_index.get_mut(&key) {
+                row_ids.remove(&row_id);
+                if row_ids.is_empty() {
+                    prefix_index.remove(&key);
+                }
+            }
+        }
+        if let Some(row_ids) = self.by_value.get_mut(entry) {
+            row_ids.remove(&row_id);
+        }
+    }
+
+    /// Insert a row under a given RowId and raise `row_max` if necessary.
+    fn insert_row(&mut self, row_id: RowId, entries: Vec<Entry>) {
+        for entry in &entries {
+            self.add_name(entry.name.clone(), row_id);
+            self.add_value(entry.clone(), row_id);
+        }
+        self.by_row_id.insert(row_id, entries);
+        self.dirty = true;
+        if row_id > self.row_max {
+            self.row_max = row_id;
+        }
+        self.log_row(row_id);
+    }
+
+    /// Add a new row with one i32
+    pub fn add_i32(&mut self, name: &str, value: i32) -> RowId {
+        self.add_row(vec![Entry::new_i32(name, value)])
+    }
+
+    /// Add a new row with one string
+    pub fn add_string(&mut self, name: &str, value: &str) -> RowId {
+        self.add_row(vec![Entry::new_string(name, value)])
+    }
+
+    /// Add a new row with multiple entries.
+    pub fn add_row(&mut self, mut entries: Vec<Entry>) -> RowId {
+        self.prepare_row(&mut entries);
+        self.add_prepared_row(entries)
+    }
+
+    /// Apply `enable_trim()` and `enable_timestamps()` to the entries of a new row.
+    fn prepare_row(&self, entries: &mut Vec<Entry>) {
+        for entry in entries.iter_mut() {
+            self.trim_value(entry);
+        }
+        self.stamp(entries);
+    }
+
+    /// Add a new row whose entries went through `prepare_row()` already.
+    fn add_prepared_row(&mut self, entries: Vec<Entry>) -> RowId {
+        let row_id = self.next();
+        self.insert_row(row_id, entries);
+        self.notify(ChangeEvent::RowAdded(row_id));
+        row_id
+    }
+
+    /// Like `add_row()`, but refuses to add the row if one of `unique_names` occurs more than once
+    /// in `entries`. Names not listed may repeat.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let entries = vec![
+    ///     Entry::new_string("name", "coche"),
+    ///     Entry::new_string("value", "car"),
+    ///     Entry::new_string("value", "automobile"),
+    /// ];
+    /// assert!(db.add_row_checked(entries, &["name"]).is_ok());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DbError::DuplicateName` with the first repeated name. Nothing is added.
+    pub fn add_row_checked(
+        &mut self,
+        entries: Vec<Entry>,
+        unique_names: &[&str],
+    ) -> Result<RowId, DbError> {
+        for name in unique_names {
+            if entries.iter().filter(|entry| entry.name == *name).count() > 1 {
+                return Err(DbError::DuplicateName(name.to_string()));
+            }
+        }
+        Ok(self.add_row(entries))
+    }
+
+    /// Register a callback that is called after rows are added or deleted and after entries are
+    /// changed with `add_or_update_entry()` or `update_value()`. Replaces a previous callback.
+    /// Bulk operations like `clear()`, `compact()` or `rename_entry()` do not report their
+    /// changes. The callback is dropped by `clone()` and not saved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use vdb::{ChangeEvent, Db};
+    /// let mut db = Db::new("test-db");
+    /// let events = Arc::new(Mutex::new(vec![]));
+    /// let log = Arc::clone(&events);
+    /// db.on_change(Box::new(move |event| log.lock().unwrap().push(event)));
+    /// let row_id = db.add_string("word", "coche");
+    /// assert_eq!(*events.lock().unwrap(), [ChangeEvent::RowAdded(row_id)]);
+    /// ```
+    pub fn on_change(&mut self, f: Box<dyn FnMut(ChangeEvent) + Send>) {
+        self.on_change = ChangeListener(Some(Mutex::new(f)));
+    }
+
+    fn notify(&mut self, event: ChangeEvent) {
+        if let Some(f) = &mut self.on_change.0 {
+            let f = f.get_mut().unwrap_or_else(PoisonError::into_inner);
+            f(event);
+        }
+    }
+
+    /// Stamp rows added from now on with a `DbDateTime` entry named `CREATED_AT` holding the
+    /// local time. The stamp is a normal entry, so it is saved and loaded with the row. Rows that
+    /// already have a `CREATED_AT` entry are left alone. The setting itself is not saved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, CREATED_AT};
+    /// let mut db = Db::new("test-db");
+    /// db.enable_timestamps();
+    /// let row_id = db.add_string("word", "coche");
+    /// assert!(db.get_datetime(row_id, CREATED_AT).is_some());
+    /// ```
+    pub fn enable_timestamps(&mut self) {
+        self.timestamps = true;
+    }
+
+    /// Trim leading and trailing whitespace from `DbString` values added from now on, so
+    /// imported values like `"car "` match equality predicates for `"car"`. This only affects
+    /// new inserts through `add_row()` and its variants, `add_row_id_entry()`,
+    /// `add_or_update_entry()`, `append_value()` and `upsert_row_by()`. Values already in the
+    /// database and values changed with `update_value()` or `replace_value()` are kept as they
+    /// are. The setting is not saved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::new("test-db");
+    /// db.enable_trim();
+    /// let row_id = db.add_string("word", " coche\n");
+    /// assert_eq!(db.get_string(row_id, "word"), Some(String::from("coche")));
+    /// ```
+    pub fn enable_trim(&mut self) {
+        self.trim_strings = true;
+    }
+
+    fn trim_value(&self, entry: &mut Entry) {
+        if !self.trim_strings {
+            return;
+        }
+        if let Data::DbString(value) = &mut entry.value {
+            let trimmed = value.trim();
+            if trimmed.len() != value.len() {
+                *value = trimmed.to_string();
+            }
+        }
+    }
+
+    fn stamp(&self, entries: &mut Vec<Entry>) {
+        if self.timestamps && !entries.iter().any(|entry| entry.name == CREATED_AT) {
+            entries.push(Entry {
+                name: String::from(CREATED_AT),
+                value: Data::now(),
+            });
+        }
+    }
+
+    /// Add a new row unless a row with exactly the same entries already exists, in which case
+    /// nothing is inserted and `None` is returned. The order of the entries does not matter and
+    /// `CREATED_AT` stamps are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row = vec![Entry::new_string("name", "coche"), Entry::new_string("value", "car")];
+    /// assert!(db.add_row_unique(row.clone()).is_some());
+    /// assert_eq!(db.add_row_unique(row), None);
+    /// ```
+    pub fn add_row_unique(&mut self, mut entries: Vec<Entry>) -> Option<RowId> {
+        for entry in &mut entries {
+            self.trim_value(entry);
+        }
+        if self.find_identical_row(&entries).is_some() {
+            None
+        } else {
+            Some(self.add_row(entries))
+        }
+    }
+
+    /// Returns a row holding exactly `entries`, in any order. Candidates come from the smallest
+    /// `by_value` set of the entries.
+    fn find_identical_row(&self, entries: &[Entry]) -> Option<RowId> {
+        let unstamped = |row: &[Entry]| -> Vec<Entry> {
+            row.iter()
+                .filter(|entry| entry.name != CREATED_AT)
+                .cloned()
+                .collect()
+        };
+        let entries = unstamped(entries);
+        let same_entries = |row: &Vec<Entry>| {
+            let row = unstamped(row);
+            row.len() == entries.len()
+                && row.iter().all(|entry| entries.contains(entry))
+                && entries.iter().all(|entry| row.contains(entry))
+        };
+        if entries.is_empty() {
+            return self
+                .by_row_id
+                .iter()
+                .find(|(_row_id, row)| same_entries(row))
+                .map(|(row_id, _row)| *row_id);
+        }
+        let mut candidates = vec![];
+        for entry in &entries {
+            match self.by_value.get(entry) {
+                Some(row_ids) => candidates.push(row_ids),
+                None => return None,
+            }
+        }
+        candidates
+            .into_iter()
+            .min_by_key(|row_ids| row_ids.len())?
+            .iter()
+            .find(|row_id| same_entries(&self.by_row_id[row_id]))
+            .cloned()
+    }
+
+    /// Add a new row with the entries of a `RowBuilder`.
+    pub fn add_row_builder(&mut self, builder: RowBuilder) -> RowId {
+        self.add_row(builder.build())
+    }
+
+    /// Add many rows at once. The new rows get consecutive RowIds, which are returned in the
+    /// order of `rows`. Index capacity is reserved up front, which makes this faster than calling
+    /// `add_row()` repeatedly when loading large data sets.
+    pub fn add_rows(&mut self, rows: Vec<Vec<Entry>>) -> Vec<RowId> {
+        let entry_count = rows.iter().map(|entries| entries.len()).sum();
+        self.by_row_id.reserve(rows.len());
+        self.by_value.reserve(entry_count);
+
+        let first = self.row_max.0 + 1;
+        self.row_max.0 += rows.len();
+        let mut row_ids = Vec::with_capacity(rows.len());
+        for (i, mut entries) in rows.into_iter().enumerate() {
+            for entry in &mut entries {
+                self.trim_value(entry);
+            }
+            self.stamp(&mut entries);
+            let row_id = RowId(first + i);
+            self.insert_row(row_id, entries);
+            self.notify(ChangeEvent::RowAdded(row_id));
+            row_ids.push(row_id);
+        }
+        row_ids
+    }
+
+    /// Copy all rows of `other` into this database. The copies get new RowIds, which are returned
+    /// in the order of the RowIds in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut spanish = Db::new("test-db");
+    /// let _row_id = spanish.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let mut french = Db::new("test-db");
+    /// let _row_id = french.add_row(vec![Entry::new_string("word", "voiture")]);
+    /// let row_ids = spanish.merge(&french);
+    /// assert_eq!(spanish.get_string(row_ids[0], "word"), Some(String::from("voiture")));
+    /// ```
+    pub fn merge(&mut self, other: &Db) -> Vec<RowId> {
+        let mut row_ids = other.find_all_row_ids();
+        row_ids.sort();
+        let rows = row_ids
+            .iter()
+            .map(|row_id| other.by_row_id[row_id].clone())
+            .collect::<Vec<Vec<Entry>>>();
+        self.add_rows(rows)
+    }
+
+    /// Start a transaction. Rows added through the returned `Transaction` are removed again by
+    /// `Transaction::rollback()` or when the transaction is dropped without `commit()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let mut transaction = db.begin();
+    /// let _row_id = transaction.add_row(vec![Entry::new_string("word", "coche")]);
+    /// transaction.rollback();
+    /// assert!(db.find_all_row_ids().is_empty());
+    /// ```
+    pub fn begin(&mut self) -> Transaction<'_> {
+        Transaction {
+            row_max: self.row_max,
+            db: self,
+            inserted: vec![],
+            created_names: vec![],
+            created_values: vec![],
+            committed: false,
+        }
+    }
+
+    /// Add a single entry to an existing row. An existing entry with the same name is overwritten.
+    /// If multiple entries with the same name exist, they will be overwritten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Upsert};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let result = db.add_or_update_entry(row_id, Entry::new_i32("add_counter", 1));
+    /// assert_eq!(result, Upsert::Inserted);
+    /// let result = db.add_or_update_entry(row_id, Entry::new_i32("add_counter", 2));
+    /// assert_eq!(result, Upsert::Updated(1));
+    /// ```
+    pub fn add_or_update_entry(&mut self, row_id: RowId, new_entry: Entry) -> Upsert {
+        let replaced = self.by_row_id.get(&row_id).map_or(0, |entries| {
+            entries
+                .iter()
+                .filter(|entry| entry.name == new_entry.name)
+                .count()
+        });
+        let name = new_entry.name.clone();
+        self.remove_by_name(row_id, &name);
+        self.add_row_id_entry(row_id, new_entry);
+        self.notify(ChangeEvent::EntryUpdated(row_id, name));
+        if replaced == 0 {
+            Upsert::Inserted
+        } else {
+            Upsert::Updated(replaced)
+        }
+    }
+
+    /// Insert `entries` as a new row, unless a row already holds the same value under `key_name`
+    /// as `entries`. Then the entries are merged into that row instead: for every name in
+    /// `entries` the old entries of that name are replaced, entries with other names are kept.
+    /// If several rows hold the key, the lowest RowId is updated. Without a `key_name` entry in
+    /// `entries` the row is always inserted. Returns the RowId of the inserted or updated row.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.upsert_row_by("word", vec![Entry::new_string("word", "coche"), Entry::new_i32("add_counter", 1)]);
+    /// let updated = db.upsert_row_by("word", vec![Entry::new_string("word", "coche"), Entry::new_i32("add_counter", 2)]);
+    /// assert_eq!(updated, row_id);
+    /// assert_eq!(db.get_i32(row_id, "add_counter"), Some(2));
+    /// ```
+    pub fn upsert_row_by(&mut self, key_name: &str, mut entries: Vec<Entry>) -> RowId {
+        for entry in &mut entries {
+            self.trim_value(entry);
+        }
+        let existing = entries
+            .iter()
+            .find(|entry| entry.name == key_name)
+            .and_then(|key| self.by_value.get(key))
+            .and_then(|row_ids| row_ids.iter().min().cloned());
+        let row_id = match existing {
+            Some(row_id) => row_id,
+            None => return self.add_row(entries),
+        };
+
+        let mut names = vec![];
+        for entry in &entries {
+            if entry.name != key_name && !names.contains(&entry.name) {
+                names.push(entry.name.clone());
+            }
+        }
+        for name in &names {
+            self.remove_by_name(row_id, name);
+        }
+        for entry in entries.into_iter().filter(|entry| entry.name != key_name) {
+            self.add_row_id_entry(row_id, entry);
+        }
+        for name in names {
+            self.notify(ChangeEvent::EntryUpdated(row_id, name));
+        }
+        row_id
+    }
+
+    /// Change the value of the first entry named `name` in row `row_id` in place. Returns `false`
+    /// if there is no such entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_i32("add_counter", 1)]);
+    /// assert!(db.update_value(row_id, "add_counter", Db::db_i32(2)));
+    /// assert_eq!(db.get_i32(row_id, "add_counter"), Some(2));
+    /// ```
+    pub fn update_value(&mut self, row_id: RowId, name: &str, new_value: Data) -> bool {
+        let entries = match self.by_row_id.get_mut(&row_id) {
+            Some(entries) => entries,
+            None => return false,
+        };
+        let entry = match Entry::get_first_by_name_mut(entries, name) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let old_entry = entry.clone();
+        entry.value = new_value;
+        let new_entry = entry.clone();
+
+        // The row may hold the old value more than once
+        if !entries.contains(&old_entry) {
+            self.remove_value(&old_entry, row_id);
+        }
+        self.add_value(new_entry, row_id);
+        self.log_row(row_id);
+        self.notify(ChangeEvent::EntryUpdated(row_id, name.to_string()));
+        true
+    }
+
+    /// Removes all entries with name 'name' and row 'row_id'. Does not delete the whole row and
+    /// leaves entries with other names.
+    pub fn remove_by_name(&mut self, row_id: RowId, name: &str) {
+        let removed = match self.by_row_id.get_mut(&row_id) {
+            Some(entries) => {
+                let (removed, kept): (Vec<Entry>, Vec<Entry>) =
+                    entries.drain(..).partition(|entry| entry.name == name);
+                *entries = kept;
+                removed
+            }
+            None => return,
+        };
+
+        if !removed.is_empty() {
+            if let Some(row_ids) = self.by_name.get_mut(name) {
+                row_ids.remove(&row_id);
+            }
+        }
+        for entry in &removed {
+            self.remove_value(entry, row_id);
+        }
+        if !removed.is_empty() {
+            self.log_row(row_id);
+        }
+    }
+
+    /// Removes one entry named `name` with value `value` from row `row_id`, keeping other entries
+    /// with the same name. Like `remove_by_name()`, the row is not deleted even if it is left
+    /// empty. Returns `false` if there is no such entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![
+    ///     Entry::new_string("value", "car"),
+    ///     Entry::new_string("value", "automobile"),
+    /// ]);
+    /// assert!(db.remove_entry(row_id, "value", &Db::db_string("automobile")));
+    /// assert_eq!(db.row_entries(row_id), Some(&[Entry::new_string("value", "car")][..]));
+    /// ```
+    pub fn remove_entry(&mut self, row_id: RowId, name: &str, value: &Data) -> bool {
+        let entry = Entry {
+            name: String::from(name),
+            value: value.clone(),
+        };
+        self.take_entry(row_id, &entry)
+    }
+
+    /// Move one entry named `name` with value `value` from row `from` to the end of row `to`.
+    /// Returns `false` and changes nothing if `from` has no such entry or `to` does not exist.
+    /// If `from` is left without entries, it is deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let coche = db.add_row(vec![Entry::new_string("translation", "car")]);
+    /// let cocina = db.add_row(vec![Entry::new_string("translation", "kitchen")]);
+    /// let moto = db.add_row(vec![
+    ///     Entry::new_string("translation", "motorbike"),
+    ///     Entry::new_string("translation", "cuisine"),
+    /// ]);
+    /// assert!(db.move_entry(moto, cocina, "translation", &Db::db_string("cuisine")));
+    /// assert!(!db.move_entry(moto, coche, "translation", &Db::db_string("cuisine")));
+    /// ```
+    pub fn move_entry(&mut self, from: RowId, to: RowId, name: &str, value: &Data) -> bool {
+        let entry = Entry {
+            name: name.to_string(),
+            value: value.clone(),
+        };
+        if !self.by_row_id.contains_key(&to) {
+            return false;
+        }
+        if from == to {
+            return self.by_row_id[&from].contains(&entry);
+        }
+        if !self.take_entry(from, &entry) {
+            return false;
+        }
+        if self.by_row_id[&from].is_empty() {
+            self.remove_by_row_id(from);
+        }
+        self.add_row_id_entry(to, entry);
+        true
+    }
+
+    /// Remove the first entry equal to `entry` from row `row_id` and update the indexes. Returns
+    /// `false` if there is no such entry.
+    fn take_entry(&mut self, row_id: RowId, entry: &Entry) -> bool {
+        let entries = match self.by_row_id.get_mut(&row_id) {
+            Some(entries) => entries,
+            None => return false,
+        };
+        let position = match entries.iter().position(|e| e == entry) {
+            Some(position) => position,
+            None => return false,
+        };
+        entries.remove(position);
+        self.dirty = true;
+        let name_left = entries.iter().any(|e| e.name == entry.name);
+        let value_left = entries.contains(entry);
+
+        if !name_left {
+            if let Some(row_ids) = self.by_name.get_mut(&entry.name) {
+                row_ids.remove(&row_id);
+            }
+        }
+        if !value_left {
+            self.remove_value(entry, row_id);
+        }
+        self.log_row(row_id);
+        true
+    }
+
+    /// Removes all entries with row 'row_id'
+    pub fn remove_by_row_id(&mut self, row_id: RowId) {
+        if let Some(entries) = self.by_row_id.remove(&row_id) {
+            self.dirty = true;
+            for entry in &entries {
+                if let Some(row_ids) = self.by_name.get_mut(&entry.name) {
+                    row_ids.remove(&row_id);
+                }
+                self.remove_value(entry, row_id);
+            }
+            self.log_row(row_id);
+            self.notify(ChangeEvent::RowDeleted(row_id));
+        }
+    }
+
+    /// Add a single entry to an existing row. Does not check if entry exists.
+    pub fn add_row_id_entry(&mut self, row_id: RowId, mut entry: Entry) {
+        self.trim_value(&mut entry);
+        self.by_row_id
+            .entry(row_id)
+            .or_default()
+            .push(entry.clone());
+        self.add_name(entry.name.clone(), row_id);
+        self.add_value(entry, row_id);
+        self.log_row(row_id);
+    }
+
+    /// Add an entry to row `row_id` unless the row already has an entry with the same name and
+    /// value. Other entries with the same name are kept, which suits multi-valued columns. Returns
+    /// `true` if the entry was added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("translation", "car")]);
+    /// assert!(db.append_value(row_id, "translation", Db::db_string("automobile")));
+    /// assert!(!db.append_value(row_id, "translation", Db::db_string("car")));
+    /// ```
+    pub fn append_value(&mut self, row_id: RowId, name: &str, value: Data) -> bool {
+        let mut entry = Entry {
+            name: name.to_string(),
+            value,
+        };
+        self.trim_value(&mut entry);
+        let exists = self
+            .by_value
+            .get(&entry)
+            .is_some_and(|row_ids| row_ids.contains(&row_id));
+        if !exists {
+            self.add_row_id_entry(row_id, entry);
+        }
+        !exists
+    }
+
+    /// Rename every entry named `old_name` to `new_name`, in all rows. Rows that already have
+    /// entries named `new_name` keep them. Returns the number of entries renamed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("value", "car")]);
+    /// assert_eq!(db.rename_entry("value", "translation"), 1);
+    /// assert_eq!(db.get_string(row_id, "translation"), Some(String::from("car")));
+    /// ```
+    pub fn rename_entry(&mut self, old_name: &str, new_name: &str) -> usize {
+        if old_name == new_name {
+            return 0;
+        }
+        let row_ids = match self.by_name.remove(old_name) {
+            Some(row_ids) => row_ids,
+            None => return 0,
+        };
+
+        let mut renamed = 0;
+        for row_id in row_ids {
+            let mut changed = vec![];
+            if let Some(entries) = self.by_row_id.get_mut(&row_id) {
+                for entry in entries.iter_mut().filter(|entry| entry.name == old_name) {
+                    changed.push(entry.clone());
+                    entry.name = new_name.to_string();
+                }
+            }
+            if changed.is_empty() {
+                continue;
+            }
+            renamed += changed.len();
+            for mut entry in changed {
+                self.remove_value(&entry, row_id);
+                entry.name = new_name.to_string();
+                self.add_value(entry, row_id);
+            }
+            self.add_name(new_name.to_string(), row_id);
+            self.log_row(row_id);
+        }
+        renamed
+    }
+
+    /// Change every entry named `name` with value `old` to `new`, in all rows. Returns the number
+    /// of entries changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("translation", "kichen")]);
+    /// let old = Db::db_string("kichen");
+    /// assert_eq!(db.replace_value("translation", &old, Db::db_string("kitchen")), 1);
+    /// assert!(db.find_row_ids_by_value("translation", &old).is_empty());
+    /// ```
+    pub fn replace_value(&mut self, name: &str, old: &Data, new: Data) -> usize {
+        let old_entry = Entry {
+            name: name.to_string(),
+            value: old.clone(),
+        };
+        let new_entry = Entry {
+            name: name.to_string(),
+            value: new,
+        };
+        if old_entry == new_entry {
+            return 0;
+        }
+        let row_ids = match self.by_value.remove(&old_entry) {
+            Some(row_ids) => row_ids,
+            None => return 0,
+        };
+
+        let mut replaced = 0;
+        for row_id in row_ids {
+            if let Some(entries) = self.by_row_id.get_mut(&row_id) {
+                for entry in entries.iter_mut().filter(|entry| **entry == old_entry) {
+                    entry.value = new_entry.value.clone();
+                    replaced += 1;
+                }
+            }
+            self.remove_value(&old_entry, row_id);
+            self.add_value(new_entry.clone(), row_id);
+            self.log_row(row_id);
+        }
+        replaced
+    }
+
+    /// Delete rows in the database. Returns the number of rows that existed and were deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_1 = db.add_row(vec![
+    ///         Entry::new_string("word", "cocina"),
+    ///         Entry::new_string("translation", "cuisine"),
+    ///         Entry::new_string("translation", "kitchen"),
+    /// ]);
+    /// let row_2 = db.add_row(vec![
+    ///         Entry::new_string("word", "coche"),
+    ///         Entry::new_string("translation", "car"),
+    /// ]);
+    /// let coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
+    /// assert_eq!(coche, Some(row_2));
+    /// assert_eq!(db.delete_rows(&[row_1, row_2]), 2);
+    /// let no_coche = db.find_first_row_id_by_value("word", &Db::db_string("coche"));
+    /// assert_eq!(no_coche, None);
+    /// ```
+    pub fn delete_rows(&mut self, row_ids: &[RowId]) -> usize {
+        let mut deleted = 0;
+        for row_id in row_ids {
+            if self.by_row_id.contains_key(row_id) {
+                self.remove_by_row_id(*row_id);
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    /// Delete every row that does not match all `predicates`. With no predicates all rows are
+    /// kept. Returns the number of rows deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// let es_en = db.add_row(vec![Entry::new_string("set", "es-en")]);
+    /// let _de_en = db.add_row(vec![Entry::new_string("set", "de-en")]);
+    /// assert_eq!(db.retain_by_predicate(&[Predicate::new_equal_string("set", "es-en")]), 1);
+    /// assert_eq!(db.find_all_row_ids(), [es_en]);
+    /// ```
+    pub fn retain_by_predicate(&mut self, predicates: &[Predicate]) -> usize {
+        let keep = self
+            .filter_row_ids(predicates, usize::MAX)
+            .into_iter()
+            .collect::<HashSet<RowId>>();
+        let delete = self
+            .by_row_id
+            .keys()
+            .filter(|row_id| !keep.contains(row_id))
+            .cloned()
+            .collect::<Vec<RowId>>();
+        self.delete_rows(&delete)
+    }
+
+    /// Delete rows whose entries equal those of a row with a lower RowId, ignoring the order of
+    /// the entries. The lowest RowId of each group of duplicates is kept. Returns the number of
+    /// rows deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let first = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let _copy = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// assert_eq!(db.dedup_rows(), 1);
+    /// assert_eq!(db.find_all_row_ids(), [first]);
+    /// ```
+    pub fn dedup_rows(&mut self) -> usize {
+        let mut row_ids = self.find_all_row_ids();
+        row_ids.sort();
+
+        let mut seen = HashSet::new();
+        let mut duplicates = vec![];
+        for row_id in row_ids {
+            let mut entries = self.by_row_id[&row_id].clone();
+            entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.value.cmp(&b.value)));
+            if !seen.insert(entries) {
+                duplicates.push(row_id);
+            }
+        }
+        self.delete_rows(&duplicates)
+    }
+
+    /// Compare the rows of this database with those of `other`. Rows are matched by RowId and
+    /// two rows are the same if they hold the same entries, in any order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let mut other = db.clone();
+    /// other.update_value(row_id, "word", Db::db_string("moto"));
+    /// assert_eq!(db.diff(&other).changed, vec![row_id]);
+    /// ```
+    pub fn diff(&self, other: &Db) -> DbDiff {
+        let sorted = |entries: &[Entry]| {
+            let mut entries = entries.to_vec();
+            entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.value.cmp(&b.value)));
+            entries
+        };
+        let mut diff = DbDiff::default();
+        for (row_id, entries) in &self.by_row_id {
+            match other.by_row_id.get(row_id) {
+                None => diff.only_in_self.push(*row_id),
+                Some(other_entries) => {
+                    if sorted(entries) != sorted(other_entries) {
+                        diff.changed.push(*row_id);
+                    }
+                }
+            }
+        }
+        diff.only_in_other = other
+            .by_row_id
+            .keys()
+            .filter(|row_id| !self.by_row_id.contains_key(row_id))
+            .cloned()
+            .collect();
+        diff.only_in_self.sort();
+        diff.only_in_other.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    /// Renumber the rows to `RowId(1)..=RowId(n)`, keeping their order, and lower `row_max`
+    /// accordingly. All indexes are rebuilt. Returns the mapping from old to new RowIds so
+    /// references kept outside the database can be updated. New rows may then get RowIds of
+    /// deleted rows again; use `compact_keep_row_max()` if that is a problem.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, RowId};
+    /// let mut db = Db::new("test-db");
+    /// let first = db.add_string("word", "coche");
+    /// let second = db.add_string("word", "moto");
+    /// db.delete_rows(&[first]);
+    /// let mapping = db.compact();
+    /// assert_eq!(mapping[&second], RowId(1));
+    /// ```
+    pub fn compact(&mut self) -> HashMap<RowId, RowId> {
+        let mut rows = self.by_row_id.drain().collect::<Vec<(RowId, Vec<Entry>)>>();
+        rows.sort_by_key(|(row_id, _entries)| *row_id);
+        self.clear();
+
+        let mut mapping = HashMap::with_capacity(rows.len());
+        for (i, (old_row_id, entries)) in rows.into_iter().enumerate() {
+            let new_row_id = RowId(i + 1);
+            self.insert_row(new_row_id, entries);
+            mapping.insert(old_row_id, new_row_id);
+        }
+        mapping
+    }
+
+    /// Like `compact()`, but keeps `row_max`, so new rows never get a RowId that was handed out
+    /// before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, RowId};
+    /// let mut db = Db::new("test-db");
+    /// let first = db.add_string("word", "coche");
+    /// let second = db.add_string("word", "moto");
+    /// db.delete_rows(&[first]);
+    /// let mapping = db.compact_keep_row_max();
+    /// assert_eq!(mapping[&second], RowId(1));
+    /// assert_eq!(db.add_string("word", "bici"), RowId(3));
+    /// ```
+    pub fn compact_keep_row_max(&mut self) -> HashMap<RowId, RowId> {
+        let row_max = self.row_max;
+        let mapping = self.compact();
+        self.set_row_max(row_max.0);
+        mapping
+    }
+
+    /// Delete all rows and reset the RowId counter. The filename is kept, so the next `save()`
+    /// overwrites the file with an empty database.
+    pub fn clear(&mut self) {
+        self.dirty = true;
+        self.row_max = RowId(0);
+        self.by_row_id.clear();
+        self.by_name.clear();
+        self.by_value.clear();
+        if let Some(prefix_index) = &mut self.prefix_index {
+            prefix_index.clear();
+        }
+        self.wal.log(&WalRecord::Cleared);
+    }
+
+    /// Delete all entries with this name in the whole database.
+    /// Does not delete all rows. Deletes matching entries in the row. The row will be kept if
+    /// there are entries left, otherwise deleted.
+    pub fn delete_entry_all(&mut self, name: &str) {
+        let row_ids = self.find_row_ids_by_name(name);
+        for row_id in row_ids {
+            self.remove_by_name(row_id, name);
+        }
+    }
+
+    /// Return row_ids of entries where an entry with name "name" exists.
+    pub fn find_row_ids_by_name(&self, name: &str) -> Vec<RowId> {
+        if let Some(rows) = self.by_name.get(name) {
+            rows.iter().cloned().collect::<Vec<RowId>>()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Return row_ids of rows with a `DbString` named `name` starting with `prefix`, sorted. The
+    /// comparison works on whole `char`s, so a prefix made by dropping characters from a word
+    /// (e.g. with `String::pop()`) always matches that word. Uses the prefix index if it was
+    /// built with `build_prefix_index()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_string("word", "cocinó");
+    /// let mut prefix = String::from("cocinó");
+    /// prefix.pop();
+    /// assert_eq!(db.find_prefix("word", &prefix), [row_id]);
+    /// ```
+    pub fn find_prefix(&self, name: &str, prefix: &str) -> Vec<RowId> {
+        let mut row_ids = self.find_by_predicate(&Predicate::new_starts_with(name, prefix));
+        row_ids.sort();
+        row_ids
+    }
+
+    /// Return row_ids of rows where an entry with any of the `names` is exactly `value`, sorted
+    /// and without duplicates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let verb = db.add_row(vec![Entry::new_string("name", "ser")]);
+    /// let form = db.add_row(vec![Entry::new_string("conjugation", "ser")]);
+    /// let value = Db::db_string("ser");
+    /// assert_eq!(db.find_value_in_any_name(&["name", "conjugation"], &value), [verb, form]);
+    /// ```
+    pub fn find_value_in_any_name(&self, names: &[&str], value: &Data) -> Vec<RowId> {
+        let mut row_ids = HashSet::new();
+        for name in names {
+            let entry = Entry {
+                name: name.to_string(),
+                value: value.clone(),
+            };
+            if let Some(found) = self.by_value.get(&entry) {
+                row_ids.extend(found.iter().cloned());
+            }
+        }
+        let mut row_ids = row_ids.into_iter().collect::<Vec<RowId>>();
+        row_ids.sort();
+        row_ids
+    }
+
+    /// Return row_ids of rows with an entry named `name` whose value is of type `kind`, whatever
+    /// the value. The result is sorted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{DataKind, Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _text = db.add_row(vec![Entry::new_string("x", "5")]);
+    /// let number = db.add_row(vec![Entry::new_i32("x", 5)]);
+    /// assert_eq!(db.find_rows_by_name_and_kind("x", DataKind::I32), [number]);
+    /// ```
+    pub fn find_rows_by_name_and_kind(&self, name: &str, kind: DataKind) -> Vec<RowId> {
+        let mut row_ids = self
+            .by_name
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|row_id| {
+                self.by_row_id[row_id]
+                    .iter()
+                    .any(|entry| entry.name == name && entry.value.kind() == kind)
+            })
+            .cloned()
+            .collect::<Vec<RowId>>();
+        row_ids.sort();
+        row_ids
+    }
+
+    /// Return row_ids of rows without any entry named `name`, sorted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Data, Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let add_date = Entry { name: String::from("add_date"), value: Data::now() };
+    /// let _dated = db.add_row(vec![Entry::new_string("word", "coche"), add_date]);
+    /// let undated = db.add_row(vec![Entry::new_string("word", "moto")]);
+    /// assert_eq!(db.find_rows_missing("add_date"), [undated]);
+    /// ```
+    pub fn find_rows_missing(&self, name: &str) -> Vec<RowId> {
+        let with_name = self.by_name.get(name);
+        let mut row_ids = self
+            .by_row_id
+            .keys()
+            .filter(|row_id| !with_name.is_some_and(|row_ids| row_ids.contains(row_id)))
+            .cloned()
+            .collect::<Vec<RowId>>();
+        row_ids.sort();
+        row_ids
+    }
+
+    /// Return row_ids of entries that are exactly "value". For partial string matches, use
+    /// Predicates.
+    pub fn find_row_ids_by_value(&self, name: &str, value: &Data) -> Vec<RowId> {
+        let entry = Entry {
+            name: name.to_string(),
+            value: value.clone(),
+        };
+        if let Some(rows) = self.by_value.get(&entry) {
+            rows.iter().cloned().collect::<Vec<RowId>>()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Return reference to first entry found in a given row.
+    pub fn find_first_row_id_by_name(&self, name: &str) -> Option<RowId> {
+        if let Some(rows) = self.by_name.get(name) {
+            rows.iter().cloned().next()
+        } else {
+            None
+        }
+    }
+
+    /// Return reference to first entry found in a given row.
+    pub fn find_first_row_id_by_value(&self, name: &str, value: &Data) -> Option<RowId> {
+        let entry = Entry {
+            name: name.to_string(),
+            value: value.clone(),
+        };
+        if let Some(rows) = self.by_value.get(&entry) {
+            rows.iter().cloned().next()
+        } else {
+            None
+        }
+    }
+
+    /// Return reference to first entry found in a given row. Returns `None` if the row does not
+    /// exist.
+    pub fn find_first_entry_by_name(&self, row_id: RowId, name: &str) -> Option<Entry> {
+        self.by_row_id
+            .get(&row_id)
+            .and_then(|entries| Entry::get_first_by_name_ref(entries, name))
+            .cloned()
+    }
+
+    /// Build a sorted index of all `DbString` values so `StartsWith` predicates no longer scan
+    /// every row. The index costs memory and is kept up to date on every change once built. It is
+    /// not saved, call this again after `load()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// db.build_prefix_index();
+    /// let row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let predicates = vec![Predicate::new_starts_with("word", "coc")];
+    /// assert_eq!(db.find_row_ids_by_predicate(&predicates, None), [row_id]);
+    /// ```
+    pub fn build_prefix_index(&mut self) {
+        let mut prefix_index: BTreeMap<(String, String), HashSet<RowId>> = BTreeMap::new();
+        for (entry, row_ids) in &self.by_value {
+            if let Data::DbString(value) = &entry.value {
+                if !row_ids.is_empty() {
+                    prefix_index
+                        .entry((entry.name.clone(), value.clone()))
+                        .or_default()
+                        .extend(row_ids.iter().cloned());
+                }
+            }
+        }
+        self.prefix_index = Some(prefix_index);
+    }
+
+    /// Lazy version of `find_by_predicate()`: RowIds are produced one by one, so `.take()` or
+    /// `.find()` stop early without collecting all matches. Equality uses the index, everything
+    /// else scans the rows. The order is unspecified. The iterator borrows the database, it can
+    /// not be changed while the iterator is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// db.add_string("word", "coche");
+    /// db.add_string("word", "moto");
+    /// db.add_string("word", "bici");
+    /// let first_two = db
+    ///     .iter_by_predicate(&Predicate::new_any("word"))
+    ///     .take(2)
+    ///     .count();
+    /// assert_eq!(first_two, 2);
+    /// ```
+    pub fn iter_by_predicate<'a>(
+        &'a self,
+        predicate: &'a Predicate,
+    ) -> impl Iterator<Item = RowId> + 'a {
+        let row_ids: Box<dyn Iterator<Item = RowId> + 'a> =
+            if predicate.predicate_type == PredicateType::Equal {
+                Box::new(
+                    self.by_value
+                        .get(&predicate.entry)
+                        .into_iter()
+                        .flatten()
+                        .cloned(),
+                )
+            } else {
+                Box::new(
+                    self.by_row_id
+                        .iter()
+                        .filter(move |(_row_id, entries)| Entry::compare_all(entries, predicate))
+                        .map(|(row_id, _entries)| *row_id),
+                )
+            };
+        row_ids
+    }
+
+    pub fn find_by_predicate(&self, predicate: &Predicate) -> Vec<RowId> {
+        if predicate.predicate_type == PredicateType::Equal {
+            if let Some(row_ids) = self.by_value.get(&predicate.entry) {
+                row_ids.iter().cloned().collect::<Vec<RowId>>()
+            } else {
+                vec![]
+            }
+        } else if let (PredicateType::StartsWith, Some(prefix_index), Data::DbString(prefix)) = (
+            &predicate.predicate_type,
+            &self.prefix_index,
+            &predicate.entry.value,
+        ) {
+            let name = &predicate.entry.name;
+            let mut row_ids = HashSet::new();
+            for ((key_name, value), found) in prefix_index.range((name.clone(), prefix.clone())..) {
+                if key_name != name || !value.starts_with(prefix.as_str()) {
+                    break;
+                }
+                row_ids.extend(found.iter().cloned());
+            }
+            row_ids.into_iter().collect::<Vec<RowId>>()
+        } else if let PredicateType::In(values) = &predicate.predicate_type {
+            let mut row_ids = HashSet::new();
+            for value in values {
+                let entry = Entry {
+                    name: predicate.entry.name.clone(),
+                    value: value.clone(),
+                };
+                if let Some(found) = self.by_value.get(&entry) {
+                    row_ids.extend(found.iter().cloned());
+                }
+            }
+            row_ids.into_iter().collect::<Vec<RowId>>()
+        } else {
+            self.by_row_id
+                .iter()
+                .filter(|(_row_id, entries)| Entry::compare_all(entries, predicate))
+                .map(|(row_id, _entries)| *row_id)
+                .collect::<Vec<RowId>>()
+        }
+    }
+
+    /// Returns all rows if no predicates are given.
+    /// The first predicate is evaluated first and should have high selectivity, i. e. evaluate to a
+    /// small number of rows, to improve execution time. The number of results can be limited with
+    /// `Some(max_results)`, which returns the `max_results` lowest matching RowIds. The limit is
+    /// applied after sorting, so all matching rows are still evaluated. Only a single equality
+    /// predicate with `Some(1)` skips the sort and takes the lowest RowId from the index. Use
+    /// `find_row_ids_by_predicate_unsorted()` to stop as soon as `max_results` rows matched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Like SQL "select name, value from testdb where name='coche' limit 15"
+    /// use vdb::{Data, Db, Entry, Predicate, RowId};
+    /// let mut db = Db::new("test-db");
+    /// let _id = db.add_row(vec![
+    ///     Entry {
+    ///         name: String::from("set"),
+    ///         value: Db::db_string("es-en"),
+    ///     },
+    ///     Entry {
+    ///         name: String::from("name"),
+    ///         value: Db::db_string("coche"),
+    ///     },
+    ///     Entry {
+    ///         name: String::from("value"),
+    ///         value: Db::db_string("car"),
+    ///     },
+    /// ]);
+    /// let predicates = vec![Predicate::new_equal_string("name", "coche")];
+    /// let row_ids = db.find_row_ids_by_predicate(&predicates, Some(15));
+    /// assert_eq!(row_ids, [RowId(1)]);
+    /// assert_eq!(db.entries_from_row_ids(&row_ids, &["name", "value"])[0][0], Entry::new_string("name", "coche"));
+    /// ```
+    /// See also select()
+    pub fn find_row_ids_by_predicate(
+        &self,
+        predicates: &[Predicate],
+        max_results: Option<usize>,
+    ) -> Vec<RowId> {
+        if let ([predicate], Some(1)) = (predicates, max_results) {
+            if predicate.predicate_type == PredicateType::Equal {
+                return self
+                    .iter_by_predicate(predicate)
+                    .min()
+                    .into_iter()
+                    .collect();
+            }
+        }
+        let mut row_ids = if predicates.is_empty() {
+            self.find_all_row_ids()
+        } else {
+            self.filter_row_ids(predicates, usize::MAX)
+        };
+        row_ids.sort();
+        row_ids.dedup();
+        if let Some(max_results) = max_results {
+            row_ids.truncate(max_results);
+        }
+        row_ids
+    }
+
+    /// Like `find_row_ids_by_predicate()`, but the equality predicate matching the fewest rows
+    /// according to `value_frequency()` is evaluated first, whatever its position in
+    /// `predicates`. The order of the other predicates is kept. Without equality predicates this
+    /// is the same as `find_row_ids_by_predicate()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate, RowId};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("name", "coche")]);
+    /// let _row_id = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("name", "moto")]);
+    /// let predicates = vec![
+    ///     Predicate::new_equal_string("set", "es-en"),
+    ///     Predicate::new_equal_string("name", "moto"),
+    /// ];
+    /// assert_eq!(db.find_row_ids_optimized(&predicates, None), [RowId(2)]);
+    /// ```
+    pub fn find_row_ids_optimized(
+        &self,
+        predicates: &[Predicate],
+        max_results: Option<usize>,
+    ) -> Vec<RowId> {
+        let most_selective = predicates
+            .iter()
+            .enumerate()
+            .filter(|(_i, predicate)| predicate.predicate_type == PredicateType::Equal)
+            .min_by_key(|(_i, predicate)| {
+                self.value_frequency(&predicate.entry.name, &predicate.entry.value)
+            })
+            .map(|(i, _predicate)| i);
+        match most_selective {
+            Some(i) if i > 0 => {
+                let mut reordered = predicates.to_vec();
+                let predicate = reordered.remove(i);
+                reordered.insert(0, predicate);
+                self.find_row_ids_by_predicate(&reordered, max_results)
+            }
+            _ => self.find_row_ids_by_predicate(predicates, max_results),
+        }
+    }
+
+    /// Like `find_row_ids_by_predicate()`, but without the final sort: RowIds come in the order
+    /// the first predicate produced them, e.g. the iteration order of the index for equality.
+    /// That order is unspecified but stable as long as the database is not changed. Duplicates
+    /// are removed, keeping the first occurrence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_string("word", "coche");
+    /// let _row_id = db.add_string("word", "moto");
+    /// let predicates = vec![Predicate::new_any("word")];
+    /// let mut row_ids = db.find_row_ids_by_predicate_unsorted(&predicates, None);
+    /// row_ids.sort();
+    /// assert_eq!(row_ids, db.find_row_ids_by_predicate(&predicates, None));
+    /// ```
+    pub fn find_row_ids_by_predicate_unsorted(
+        &self,
+        predicates: &[Predicate],
+        max_results: Option<usize>,
+    ) -> Vec<RowId> {
+        let row_ids = if predicates.is_empty() {
+            self.find_all_row_ids()
+        } else {
+            self.filter_row_ids(predicates, max_results.unwrap_or(usize::MAX))
+        };
+        let mut seen = HashSet::new();
+        row_ids
+            .into_iter()
+            .filter(|row_id| seen.insert(*row_id))
+            .take(max_results.unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// At most `max_results` RowIds matching all predicates, in the order the first predicate
+    /// produced them. An equality first predicate walks its index set lazily and stops as soon
+    /// as `max_results` rows matched.
+    fn filter_row_ids(&self, predicates: &[Predicate], max_results: usize) -> Vec<RowId> {
+        let (predicate0, rest) = match predicates.split_first() {
+            Some(split) => split,
+            None => return self.find_all_row_ids(),
+        };
+        if predicate0.predicate_type == PredicateType::Equal {
+            return self
+                .iter_by_predicate(predicate0)
+                .filter(|row_id| rest.iter().all(|p| self.match_row(*row_id, p)))
+                .take(max_results)
+                .collect();
+        }
+        let mut row_ids = self.find_by_predicate(predicate0);
+        for predicate in rest {
+            row_ids.retain(|row_id| self.match_row(*row_id, predicate));
+        }
+        row_ids.truncate(max_results);
+        row_ids
+    }
+
+    /// Returns the RowIds matching `query`, sorted. Each `and` group of the query is evaluated
+    /// like `find_row_ids_by_predicate()` and the results are merged. The limit is applied
+    /// after sorting.
+    pub fn run(&self, query: &Query) -> Vec<RowId> {
+        let mut row_ids = if query.groups.is_empty() {
+            self.find_all_row_ids()
+        } else {
+            query
+                .groups
+                .iter()
+                .flat_map(|group| self.filter_row_ids(group, usize::MAX))
+                .collect()
+        };
+        row_ids.sort();
+        row_ids.dedup();
+        if let Some(limit) = query.limit {
+            row_ids.truncate(limit);
+        }
+        row_ids
+    }
+
+    /// Like `find_row_ids_by_predicate()`, but returns one page of the sorted results: `offset`
+    /// rows are skipped, then at most `limit` rows are returned. An offset beyond the end of the
+    /// results or a `limit` of zero returns an empty list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// let row_ids = (0..5)
+    ///     .map(|i| db.add_row(vec![Entry::new_i32("number", i)]))
+    ///     .collect::<Vec<_>>();
+    /// let predicates = vec![Predicate::new_any_string("number")];
+    /// let page = db.find_row_ids_by_predicate_paged(&predicates, 2, 2);
+    /// assert_eq!(page, &row_ids[2..4]);
+    /// ```
+    pub fn find_row_ids_by_predicate_paged(
+        &self,
+        predicates: &[Predicate],
+        offset: usize,
+        limit: usize,
+    ) -> Vec<RowId> {
+        self.find_row_ids_by_predicate(predicates, None)
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns one page of the rows matching `predicates`, sorted by the first entry named
+    /// `sort_name` like `sort_row_ids_by()`: `offset` rows are skipped, then at most `limit`
+    /// rows are returned with all their entries. Rows with the same sort value stay in RowId
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// for word in &["moto", "coche", "barco", "tren"] {
+    ///     db.add_string("word", word);
+    /// }
+    /// let page = db.page_sorted(&[Predicate::new_any("word")], "word", 1, 2);
+    /// assert_eq!(page, [[Entry::new_string("word", "coche")], [Entry::new_string("word", "moto")]]);
+    /// ```
+    pub fn page_sorted(
+        &self,
+        predicates: &[Predicate],
+        sort_name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<Vec<Entry>> {
+        let mut row_ids = self.find_row_ids_by_predicate(predicates, None);
+        self.sort_row_ids_by(&mut row_ids, sort_name, false);
+        row_ids
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|row_id| self.by_row_id[row_id].clone())
+            .collect()
+    }
+
+    /// Returns the `n` highest RowIds in the database, most recent (highest) first. RowIds of
+    /// deleted rows are skipped. The RowIds are collected once and only the `n` highest are
+    /// sorted, so gaps left by deletions cost nothing.
+    pub fn last_n_rows(&self, n: usize) -> Vec<RowId> {
+        let mut row_ids = self.find_all_row_ids();
+        if n == 0 {
+            return vec![];
+        }
+        if n < row_ids.len() {
+            row_ids.select_nth_unstable_by(n - 1, |a, b| b.cmp(a));
+            row_ids.truncate(n);
+        }
+        row_ids.sort_by(|a, b| b.cmp(a));
+        row_ids
+    }
+
+    /// Iterate over all rows without cloning their entries. The order is unspecified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "cocina")]);
+    /// let long_words = db
+    ///     .iter_rows()
+    ///     .filter(|(_row_id, entries)| entries.iter().any(|e| e.value.to_string().len() > 5))
+    ///     .count();
+    /// assert_eq!(long_words, 1);
+    /// ```
+    pub fn iter_rows(&self) -> impl Iterator<Item = (RowId, &[Entry])> {
+        self.by_row_id
+            .iter()
+            .map(|(row_id, entries)| (*row_id, entries.as_slice()))
+    }
+
+    /// Iterate over every entry of every row together with its RowId. The order of the rows is
+    /// unspecified, entries of a row come in the order they are stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche"), Entry::new_string("value", "car")]);
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "moto")]);
+    /// let words = db.iter_entries().filter(|(_row_id, entry)| entry.name == "word").count();
+    /// assert_eq!(words, 2);
+    /// ```
+    pub fn iter_entries(&self) -> impl Iterator<Item = (RowId, &Entry)> + '_ {
+        self.by_row_id
+            .iter()
+            .flat_map(|(row_id, entries)| entries.iter().map(move |entry| (*row_id, entry)))
+    }
+
+    /// Check that every entry with a name listed in `spec` holds the expected type. All
+    /// violations are returned, sorted by RowId. Entries with unlisted names are not checked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{DataKind, Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let row_id = db.add_row(vec![Entry::new_string("add_counter", "1")]);
+    /// let errors = db
+    ///     .validate_schema(&[("word", DataKind::String), ("add_counter", DataKind::I32)])
+    ///     .unwrap_err();
+    /// assert_eq!(errors[0].row_id, row_id);
+    /// ```
+    pub fn validate_schema(&self, spec: &[(&str, DataKind)]) -> Result<(), Vec<SchemaError>> {
+        let mut errors = vec![];
+        for (name, expected) in spec {
+            for (row_id, entries) in &self.by_row_id {
+                for entry in entries.iter().filter(|entry| entry.name == *name) {
+                    let found = entry.value.kind();
+                    if found != *expected {
+                        errors.push(SchemaError {
+                            row_id: *row_id,
+                            name: entry.name.clone(),
+                            expected: *expected,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.sort_by_key(|error| error.row_id);
+            Err(errors)
+        }
+    }
+
+    /// Returns the number of rows matching all predicates, like
+    /// `find_row_ids_by_predicate(predicates, None).len()` but without collecting the RowIds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "cocina")]);
+    /// assert_eq!(db.count_by_predicate(&[Predicate::new_starts_with("word", "coc")]), 2);
+    /// ```
+    pub fn count_by_predicate(&self, predicates: &[Predicate]) -> usize {
+        let (predicate0, rest) = match predicates.split_first() {
+            Some(split) => split,
+            None => return self.by_row_id.len(),
+        };
+
+        if predicate0.predicate_type == PredicateType::Equal {
+            if let Some(row_ids) = self.by_value.get(&predicate0.entry) {
+                if rest.is_empty() {
+                    row_ids.len()
+                } else {
+                    row_ids
+                        .iter()
+                        .filter(|&row_id| rest.iter().all(|p| self.match_row(*row_id, p)))
+                        .count()
+                }
+            } else {
+                0
+            }
+        } else {
+            self.by_row_id
+                .values()
+                .filter(|entries| predicates.iter().all(|p| Entry::compare_all(entries, p)))
+                .count()
+        }
+    }
+
+    /// Returns `true` if at least one row matches all predicates. Stops at the first match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// db.add_string("word", "coche");
+    /// assert!(db.any_by_predicate(&[Predicate::new_equal_string("word", "coche")]));
+    /// assert!(!db.any_by_predicate(&[Predicate::new_equal_string("word", "moto")]));
+    /// ```
+    pub fn any_by_predicate(&self, predicates: &[Predicate]) -> bool {
+        self.first_by_predicate(predicates).is_some()
+    }
+
+    /// Returns a row matching all predicates, or `None`. Stops at the first match, which is not
+    /// necessarily the lowest RowId. With no predicates any row matches.
+    pub fn first_by_predicate(&self, predicates: &[Predicate]) -> Option<RowId> {
+        match predicates.split_first() {
+            Some((predicate0, rest)) => self
+                .iter_by_predicate(predicate0)
+                .find(|row_id| rest.iter().all(|p| self.match_row(*row_id, p))),
+            None => self.by_row_id.keys().next().cloned(),
+        }
+    }
+
+    /// Read-only access to the index from entry name to the rows holding an entry of that name,
+    /// e.g. for set operations the query functions do not offer. Names may map to an empty set
+    /// after their entries were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashSet;
+    /// use vdb::{Db, Entry, RowId};
+    /// let mut db = Db::new("test-db");
+    /// let both = db.add_row(vec![Entry::new_string("word", "coche"), Entry::new_string("audio", "coche.ogg")]);
+    /// let _word_only = db.add_row(vec![Entry::new_string("word", "moto")]);
+    /// let _audio_only = db.add_row(vec![Entry::new_string("audio", "tren.ogg")]);
+    /// let index = db.index_by_name();
+    /// let with_audio = index["word"].intersection(&index["audio"]).cloned().collect::<HashSet<RowId>>();
+    /// assert_eq!(with_audio, [both].iter().cloned().collect());
+    /// ```
+    pub fn index_by_name(&self) -> &HashMap<String, HashSet<RowId>> {
+        &self.by_name
+    }
+
+    /// Read-only access to the index from entry to the rows holding that entry, see
+    /// `index_by_name()`. Entries may map to an empty set after they were removed.
+    pub fn index_by_value(&self) -> &HashMap<Entry, HashSet<RowId>> {
+        &self.by_value
+    }
+
+    /// Returns the names used by entries in any row, sorted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("word", "coche"), Entry::new_string("value", "car")]);
+    /// assert_eq!(db.column_names(), ["value", "word"]);
+    /// ```
+    pub fn column_names(&self) -> Vec<String> {
+        let mut names = self
+            .by_name
+            .iter()
+            .filter(|(_name, row_ids)| !row_ids.is_empty())
+            .map(|(name, _row_ids)| name.clone())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    /// Returns for every entry name the number of rows with at least one entry of that name.
+    pub fn column_counts(&self) -> HashMap<String, usize> {
+        self.by_name
+            .iter()
+            .filter(|(_name, row_ids)| !row_ids.is_empty())
+            .map(|(name, row_ids)| (name.clone(), row_ids.len()))
+            .collect()
+    }
+
+    /// Returns the number of distinct values stored under `name`. Together with
+    /// `value_frequency()` this helps to pick a selective first predicate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("word", "coche")]);
+    /// let _row_id = db.add_row(vec![Entry::new_string("set", "es-en"), Entry::new_string("word", "moto")]);
+    /// assert_eq!(db.name_cardinality("set"), 1);
+    /// assert_eq!(db.name_cardinality("word"), 2);
+    /// assert_eq!(db.value_frequency("set", &Db::db_string("es-en")), 2);
+    /// ```
+    pub fn name_cardinality(&self, name: &str) -> usize {
+        self.by_value
+            .iter()
+            .filter(|(entry, row_ids)| entry.name == name && !row_ids.is_empty())
+            .count()
+    }
+
+    /// Returns the number of rows holding `value` under `name`, i.e. how many rows an equality
+    /// predicate on it matches.
+    pub fn value_frequency(&self, name: &str, value: &Data) -> usize {
+        let entry = Entry {
+            name: String::from(name),
+            value: value.clone(),
+        };
+        self.by_value.get(&entry).map_or(0, |row_ids| row_ids.len())
+    }
+
+    /// Returns every distinct value stored under `name`. Values are sorted, strings lexically and
+    /// numbers numerically. Different types are ordered `DbI32` < `DbI64` < `DbString` <
+    /// `DbDateTime` < `DbBytes`.
+    pub fn distinct_values(&self, name: &str) -> Vec<Data> {
+        let mut values = self
+            .by_value
+            .iter()
+            .filter(|(entry, row_ids)| entry.name == name && !row_ids.is_empty())
+            .map(|(entry, _row_ids)| entry.value.clone())
+            .collect::<Vec<Data>>();
+        values.sort();
+        values
+    }
+
+    /// Returns the number of rows for each distinct value stored under `name`. A row holding the
+    /// same value several times is counted once.
+    pub fn count_by_value(&self, name: &str) -> HashMap<Data, usize> {
+        self.by_value
+            .iter()
+            .filter(|(entry, row_ids)| entry.name == name && !row_ids.is_empty())
+            .map(|(entry, row_ids)| (entry.value.clone(), row_ids.len()))
+            .collect()
+    }
+
+    /// Returns the sum of all `DbI32` values stored under `name`. Other types are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_i32("add_counter", 3);
+    /// let _row_id = db.add_i32("add_counter", 4);
+    /// assert_eq!(db.sum_i32("add_counter"), 7);
+    /// assert_eq!(db.avg_i32("add_counter"), Some(3.5));
+    /// ```
+    pub fn sum_i32(&self, name: &str) -> i64 {
+        self.i32_values(name).map(i64::from).sum()
+    }
+
+    /// Returns the average of all `DbI32` values stored under `name`, or `None` if there are none.
+    /// Other types are ignored.
+    pub fn avg_i32(&self, name: &str) -> Option<f64> {
+        let (count, sum) = self
+            .i32_values(name)
+            .fold((0usize, 0i64), |(count, sum), value| {
+                (count + 1, sum + i64::from(value))
+            });
+        if count == 0 {
+            None
+        } else {
+            Some(sum as f64 / count as f64)
+        }
+    }
+
+    /// Returns the smallest `DbI32` value stored under `name`. Other types are ignored.
+    pub fn min_i32(&self, name: &str) -> Option<i32> {
+        self.i32_values(name).min()
+    }
+
+    /// Returns the largest `DbI32` value stored under `name`. Other types are ignored.
+    pub fn max_i32(&self, name: &str) -> Option<i32> {
+        self.i32_values(name).max()
+    }
+
+    /// Returns the sum of all `DbI64` and `DbI32` values stored under `name`. Other types are
+    /// ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![Entry::new_i64("bytes", i64::MAX)]);
+    /// let _row_id = db.add_row(vec![Entry::new_i32("bytes", 1)]);
+    /// assert_eq!(db.sum_i64("bytes"), i128::from(i64::MAX) + 1);
+    /// assert_eq!(db.max_i64("bytes"), Some(i64::MAX));
+    /// ```
+    pub fn sum_i64(&self, name: &str) -> i128 {
+        self.i64_values(name).map(i128::from).sum()
+    }
+
+    /// Returns the average of all `DbI64` and `DbI32` values stored under `name`, or `None` if
+    /// there are none. Other types are ignored.
+    pub fn avg_i64(&self, name: &str) -> Option<f64> {
+        let (count, sum) = self
+            .i64_values(name)
+            .fold((0usize, 0i128), |(count, sum), value| {
+                (count + 1, sum + i128::from(value))
+            });
+        if count == 0 {
+            None
+        } else {
+            Some(sum as f64 / count as f64)
+        }
+    }
+
+    /// Returns the smallest `DbI64` or `DbI32` value stored under `name`. Other types are ignored.
+    pub fn min_i64(&self, name: &str) -> Option<i64> {
+        self.i64_values(name).min()
+    }
+
+    /// Returns the largest `DbI64` or `DbI32` value stored under `name`. Other types are ignored.
+    pub fn max_i64(&self, name: &str) -> Option<i64> {
+        self.i64_values(name).max()
+    }
+
+    /// Returns the earliest `DbDateTime` value stored under `name`. Other types are ignored.
+    pub fn min_datetime(&self, name: &str) -> Option<NaiveDateTime> {
+        self.datetime_values(name).min()
+    }
+
+    /// Returns the latest `DbDateTime` value stored under `name`. Other types are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// for date in &["2018-03-01 10:00:00", "2018-05-01 10:00:00"] {
+    ///     let _row_id = db.add_row(vec![Entry {
+    ///         name: String::from("add_date"),
+    ///         value: Db::db_datetime(date).unwrap(),
+    ///     }]);
+    /// }
+    /// let latest = db.max_datetime("add_date").unwrap();
+    /// assert_eq!(latest.to_string(), "2018-05-01 10:00:00");
+    /// ```
+    pub fn max_datetime(&self, name: &str) -> Option<NaiveDateTime> {
+        self.datetime_values(name).max()
+    }
+
+    /// Sort `row_ids` by the value of the first entry named `name` in each row. Values of
+    /// different types are ordered `DbI32` < `DbI64` < `DbString` < `DbDateTime` < `DbBytes`.
+    /// Rows without such an entry are sorted last, also when `descending` is set. The sort is
+    /// stable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let coche = db.add_row(vec![Entry::new_string("word", "coche")]);
+    /// let barco = db.add_row(vec![Entry::new_string("word", "barco")]);
+    /// let mut row_ids = vec![coche, barco];
+    /// db.sort_row_ids_by(&mut row_ids, "word", false);
+    /// assert_eq!(row_ids, [barco, coche]);
+    /// ```
+    pub fn sort_row_ids_by(&self, row_ids: &mut [RowId], name: &str, descending: bool) {
+        let value = |row_id: &RowId| {
+            self.by_row_id
+                .get(row_id)
+                .and_then(|entries| entries.iter().find(|entry| entry.name == name))
+                .map(|entry| &entry.value)
+        };
+        row_ids.sort_by(|a, b| match (value(a), value(b)) {
+            (Some(a), Some(b)) if descending => b.cmp(a),
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+    }
+
+    /// Returns all RowIds ordered by the `DbDateTime` of the first entry named `name`, e.g. most
+    /// recently added first with `descending`. Rows without such an entry, or where it holds
+    /// another type, are sorted last in RowId order. Rows with the same datetime keep RowId order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let old = db.add_row(vec![Entry::new_datetime_str("add_date", "2018-01-01 10:00:00").unwrap()]);
+    /// let new = db.add_row(vec![Entry::new_datetime_str("add_date", "2018-05-01 10:00:00").unwrap()]);
+    /// assert_eq!(db.row_ids_sorted_by_datetime("add_date", true), [new, old]);
+    /// ```
+    pub fn row_ids_sorted_by_datetime(&self, name: &str, descending: bool) -> Vec<RowId> {
+        let mut row_ids = self.find_all_row_ids();
+        row_ids.sort();
+        let datetime = |row_id: &RowId| self.get_datetime(*row_id, name);
+        row_ids.sort_by(|a, b| match (datetime(a), datetime(b)) {
+            (Some(a), Some(b)) if descending => b.cmp(&a),
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+        row_ids
+    }
+
+    /// Returns the number of rows in the database
+    pub fn row_count(&self) -> usize {
+        self.by_row_id.len()
+    }
+
+    /// Returns the number of entries in all rows
+    pub fn entry_count(&self) -> usize {
+        self.by_row_id.values().map(|entries| entries.len()).sum()
+    }
+
+    /// Returns all rows in the database
+    pub fn find_all_row_ids(&self) -> Vec<RowId> {
+        self.by_row_id.keys().cloned().collect::<Vec<RowId>>()
+    }
+
+    #[cfg(test)]
+    pub fn find_entries_by_predicate(
+        &self,
+        predicates: &[Predicate],
+        entries: &[&str],
+    ) -> Vec<Vec<Entry>> {
+        self.select(predicates, entries, None)
+    }
+
+    /// Query and projection in one step: finds rows like `find_row_ids_by_predicate()` and
+    /// returns their entries named `names` like `entries_from_row_ids()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Like SQL "select name, translation from test_db where set='es-en'"
+    /// use vdb::{Db, Entry, Predicate};
+    /// let mut db = Db::new("test-db");
+    /// let _row_id = db.add_row(vec![
+    ///     Entry::new_string("set", "es-en"),
+    ///     Entry::new_string("name", "coche"),
+    ///     Entry::new_string("translation", "car"),
+    /// ]);
+    /// let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+    /// let rows = db.select(&predicates, &["name", "translation"], None);
+    /// assert_eq!(
+    ///     rows,
+    ///     [[
+    ///         Entry::new_string("name", "coche"),
+    ///         Entry::new_string("translation", "car"),
+    ///     ]]
+    /// );
+    /// ```
+    pub fn select<S: AsRef<str>>(
+        &self,
+        predicates: &[Predicate],
+        names: &[S],
+        max_results: Option<usize>,
+    ) -> Vec<Vec<Entry>> {
+        let row_ids = self.find_row_ids_by_predicate(predicates, max_results);
+        self.entries_from_row_ids(&row_ids, names)
+    }
+
+    /// Returns entries for given row_ids. The result has one list per RowId, in the order of
+    /// `row_ids`. Each list holds the entries in the order of `names`; several entries with the
+    /// same name keep the order they were added in. RowIds that no longer exist (e.g. rows
+    /// deleted since the query) are skipped. `names` can be given as `&str`s or `String`s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("name", "coche")]);
+    /// let names = vec![String::from("name")];
+    /// assert_eq!(
+    ///     db.entries_from_row_ids(&[row_id], &names),
+    ///     db.entries_from_row_ids(&[row_id], &["name"])
+    /// );
+    /// ```
+    pub fn entries_from_row_ids<S: AsRef<str>>(
+        &self,
+        row_ids: &[RowId],
+        names: &[S],
+    ) -> Vec<Vec<Entry>> {
+        row_ids
+            .iter()
+            .filter_map(|row_id| self.project_row(*row_id, names))
+            .collect()
+    }
+
+    /// Like `entries_from_row_ids()`, but keyed by RowId for random access. Missing RowIds are
+    /// skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_row(vec![Entry::new_string("name", "coche")]);
+    /// let map = db.entries_map_from_row_ids(&[row_id], &["name"]);
+    /// assert_eq!(map[&row_id], [Entry::new_string("name", "coche")]);
+    /// ```
+    pub fn entries_map_from_row_ids<S: AsRef<str>>(
+        &self,
+        row_ids: &[RowId],
+        names: &[S],
+    ) -> HashMap<RowId, Vec<Entry>> {
+        row_ids
+            .iter()
+            .filter_map(|row_id| {
+                self.project_row(*row_id, names)
+                    .map(|entries| (*row_id, entries))
+            })
+            .collect()
+    }
+
+    /// Entries of row `row_id` in the order of `names`, or `None` if the row does not exist.
+    fn project_row<S: AsRef<str>>(&self, row_id: RowId, names: &[S]) -> Option<Vec<Entry>> {
+        let entries = self.by_row_id.get(&row_id)?;
+        let mut ordered: Vec<Entry> = vec![];
+        for name in names {
+            for entry in entries.iter().filter(|entry| entry.name == name.as_ref()) {
+                ordered.push(entry.clone());
+            }
+        }
+        Some(ordered)
+    }
+
+    /// Returns the highest RowId handed out so far. New rows get higher RowIds, even if the row
+    /// with this RowId was deleted. The value is saved with the database.
+    pub fn row_max(&self) -> usize {
+        self.row_max.0
+    }
+
+    /// Raise or lower the highest RowId handed out so far, e.g. to reserve RowIds stored
+    /// elsewhere. The value is never set below the highest existing RowId. `clear()` and
+    /// `compact()` reset it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, RowId};
+    /// let mut db = Db::new("test-db");
+    /// db.set_row_max(100);
+    /// assert_eq!(db.add_string("word", "coche"), RowId(101));
+    /// ```
+    pub fn set_row_max(&mut self, value: usize) {
+        let highest = self.by_row_id.keys().max().map_or(0, |row_id| row_id.0);
+        let row_max = RowId(value.max(highest));
+        if row_max != self.row_max {
+            self.row_max = row_max;
+            self.dirty = true;
+            self.wal.log(&WalRecord::RowMax(self.row_max));
+        }
+    }
+
+    /// Returns `true` if the row exists, i.e. it was added and not deleted since.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::Db;
+    /// let mut db = Db::new("test-db");
+    /// let row_id = db.add_string("word", "coche");
+    /// assert!(db.contains_row(row_id));
+    /// db.delete_rows(&[row_id]);
+    /// assert!(!db.contains_row(row_id));
+    /// ```
+    pub fn contains_row(&self, row_id: RowId) -> bool {
+        self.by_row_id.contains_key(&row_id)
+    }
+
+    /// Returns all entries of a row in the order they were added, or `None` if the row does not
+    /// exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vdb::{Db, Entry};
+    /// let mut db = Db::new("test-db");
+    /// let row = vec![Entry::new_string("word", "coche"), Entry::new_i32("add_counter", 1)];
+    /// let row_id = db.add_row(row.clone());
+    /// assert_eq!(db.row_entries(row_id), Some(row.as_slice()));
+    /// ```
+    pub fn row_entries(&self, row_id: RowId) -> Option<&[Entry]> {
+        self.by_row_id
+            .get(&row_id)
+            .map(|entries| entries.as_slice())
+    }
+
+    /// Returns the value of the first entry named `name` in row `row_id`.
+    fn first_value(&self, row_id: RowId, name: &str) -> Option<&Data> {
+        self.by_row_id
+            .get(&row_id)
+            .and_then(|entries| Entry::get_first_by_name_ref(entries, name))
+            .map(|entry| &entry.value)
+    }
+
+    /// Iterate over the values of all entries named `name`.
+    fn values_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Data> + 'a {
+        self.by_name
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(move |row_id| self.by_row_id.get(row_id))
+            .flatten()
+            .filter(move |entry| entry.name == name)
+            .map(|entry| &entry.value)
+    }
+
+    /// Iterate over all `DbI32` values of entries named `name`.
+    fn i32_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = i32> + 'a {
+        self.values_named(name).filter_map(|value| match value {
+            Data::DbI32(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    /// Iterate over all `DbI64` and `DbI32` values of entries named `name`.
+    fn i64_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = i64> + 'a {
+        self.values_named(name).filter_map(|value| match value {
+            Data::DbI64(value) => Some(*value),
+            Data::DbI32(value) => Some(i64::from(*value)),
+            _ => None,
+        })
+    }
+
+    /// Iterate over all `DbDateTime` values of entries named `name`.
+    fn datetime_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = NaiveDateTime> + 'a {
+        self.values_named(name).filter_map(|value| match value {
+            Data::DbDateTime(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    /// Check if a predicate is true for a given row_id.
+    fn match_row(&self, row_id: RowId, predicate: &Predicate) -> bool {
+        let entries = &self.by_row_id[&row_id];
+        Entry::compare_all(entries, predicate)
+    }
+
+    fn next(&mut self) -> RowId {
+        self.row_max.0 += 1;
+        self.row_max
+    }
+
+    fn build_filename(name: &str) -> String {
+        format!("{}/{}", SAVE_DIR, name)
+    }
+
+    #[cfg(test)]
+    pub fn debug_rows(&self, row_ids: &[RowId]) -> Vec<Vec<Entry>> {
+        let mut result: Vec<Vec<Entry>> = vec![];
+        for row_id in row_ids {
+            let entries = &self.by_row_id[row_id];
+            result.push(entries.clone());
+        }
+        result
+    }
+}
+
+/// Read-only database handle that is cheap to clone and can be shared between threads. Created
+/// with `Db::into_shared()`. All `&self` methods of `Db` are available through `Deref`.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::{Db, Entry, Predicate};
+/// use std::thread;
+/// let mut db = Db::new("test-db");
+/// let _row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+/// let shared = db.into_shared();
+/// let handle = {
+///     let shared = shared.clone();
+///     thread::spawn(move || shared.count_by_predicate(&[Predicate::new_equal_string("word", "coche")]))
+/// };
+/// assert_eq!(handle.join().unwrap(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SharedDb(Arc<Db>);
+
+impl SharedDb {
+    /// Get back a writable database. The data is cloned only if other handles still exist.
+    pub fn into_db(self) -> Db {
+        Arc::try_unwrap(self.0).unwrap_or_else(|db| (*db).clone())
+    }
+}
+
+impl Deref for SharedDb {
+    type Target = Db;
+
+    fn deref(&self) -> &Db {
+        &self.0
+    }
+}
+
+/// Database that can be shared between threads and modified by all of them. Reads run
+/// concurrently, writes are serialized. A lock poisoned by a panicking thread is recovered, the
+/// database stays usable.
+///
+/// Methods not wrapped directly are available through `read()` and `write()`.
+///
+/// # Examples
+///
+/// ```
+/// use vdb::{ConcurrentDb, Db, Entry};
+/// let db = ConcurrentDb::new(Db::new("test-db"));
+/// let row_id = db.add_row(vec![Entry::new_string("word", "coche")]);
+/// assert_eq!(db.read(|db| db.get_string(row_id, "word")), Some(String::from("coche")));
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentDb(RwLock<Db>);
+
+impl ConcurrentDb {
+    pub fn new(db: Db) -> ConcurrentDb {
+        ConcurrentDb(RwLock::new(db))
+    }
+
+    /// Load a database, see `Db::load()`.
+    pub fn load(filename: &str) -> Result<ConcurrentDb, DbError> {
+        Ok(ConcurrentDb::new(Db::load(filename)?))
+    }
+
+    /// Save the database, see `Db::save()`.
+    pub fn save(&self) -> Result<(), DbError> {
+        self.write_lock().save()
+    }
+
+    /// Run `f` with shared read access to the database.
+    pub fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Db) -> R,
+    {
+        f(&self.read_lock())
+    }
+
+    /// Run `f` with exclusive write access to the database.
+    pub fn write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Db) -> R,
+    {
+        f(&mut self.write_lock())
+    }
+
+    /// See `Db::add_row()`.
+    pub fn add_row(&self, entries: Vec<Entry>) -> RowId {
+        self.write_lock().add_row(entries)
+    }
+
+    /// See `Db::add_or_update_entry()`.
+    pub fn add_or_update_entry(&self, row_id: RowId, new_entry: Entry) -> Upsert {
+        self.write_lock().add_or_update_entry(row_id, new_entry)
+    }
+
+    /// See `Db::delete_rows()`.
+    pub fn delete_rows(&self, row_ids: &[RowId]) -> usize {
+        self.write_lock().delete_rows(row_ids)
+    }
+
+    /// See `Db::find_row_ids_by_predicate()`.
+    pub fn find_row_ids_by_predicate(
+        &self,
+        predicates: &[Predicate],
+        max_results: Option<usize>,
+    ) -> Vec<RowId> {
+        self.read_lock()
+            .find_row_ids_by_predicate(predicates, max_results)
+    }
+
+    /// See `Db::entries_from_row_ids()`.
+    pub fn entries_from_row_ids<S: AsRef<str>>(
+        &self,
+        row_ids: &[RowId],
+        names: &[S],
+    ) -> Vec<Vec<Entry>> {
+        self.read_lock().entries_from_row_ids(row_ids, names)
+    }
+
+    /// Returns the wrapped database.
+    pub fn into_inner(self) -> Db {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, Db> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, Db> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A group of inserts that can be undone together. Created with `Db::begin()`.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    db: &'a mut Db,
+    row_max: RowId,
+    inserted: Vec<RowId>,
+    /// Index keys that did not exist before this transaction added them.
+    created_names: Vec<String>,
+    created_values: Vec<Entry>,
+    committed: bool,
+}
+
+impl<'a> Transaction<'a> {
+    /// Read access to the database, including the rows added in this transaction.
+    pub fn db(&self) -> &Db {
+        self.db
+    }
+
+    /// Add a new row with multiple entries, see `Db::add_row()`.
+    pub fn add_row(&mut self, mut entries: Vec<Entry>) -> RowId {
+        self.db.prepare_row(&mut entries);
+        for entry in &entries {
+            if !self.db.by_name.contains_key(&entry.name) {
+                self.created_names.push(entry.name.clone());
+            }
+            if !self.db.by_value.contains_key(entry) {
+                self.created_values.push(entry.clone());
+            }
+        }
+        let row_id = self.db.add_prepared_row(entries);
+        self.inserted.push(row_id);
+        row_id
+    }
+
+    /// Keep all rows added in this transaction.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Remove all rows added in this transaction and restore the RowId counter.
+    pub fn rollback(self) {}
+
+    fn undo(&mut self) {
+        for row_id in self.inserted.drain(..).rev() {
+            self.db.remove_by_row_id(row_id);
+        }
+        for name in self.created_names.drain(..) {
+            if self.db.by_name.get(&name).is_some_and(HashSet::is_empty) {
+                self.db.by_name.remove(&name);
+            }
+        }
+        for entry in self.created_values.drain(..) {
+            if self.db.by_value.get(&entry).is_some_and(HashSet::is_empty) {
+                self.db.by_value.remove(&entry);
+            }
+        }
+        self.db.row_max = self.row_max;
+        self.db.wal.log(&WalRecord::RowMax(self.row_max));
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.undo();
+        }
+    }
+}
+
+/// Decompress a gzip compressed database file
+#[cfg(feature = "flate2")]
+fn decompress(contents: &[u8]) -> Result<Vec<u8>, DbError> {
+    let mut decompressed = vec![];
+    GzDecoder::new(contents).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Decompress a gzip compressed database file while reading it
+#[cfg(feature = "flate2")]
+fn decompress_reader<R: Read>(reader: R) -> Result<io::BufReader<GzDecoder<R>>, DbError> {
+    Ok(io::BufReader::new(GzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "flate2"))]
+fn decompress(_contents: &[u8]) -> Result<Vec<u8>, DbError> {
+    Err(flate2_disabled())
+}
+
+#[cfg(not(feature = "flate2"))]
+fn decompress_reader<R: Read>(_reader: R) -> Result<R, DbError> {
+    Err(flate2_disabled())
+}
+
+#[cfg(not(feature = "flate2"))]
+fn flate2_disabled() -> DbError {
+    DbError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "database file is gzip compressed, enable the flate2 feature to load it",
+    ))
+}
+
+/// Split CSV text into records. Quoted fields may contain commas, line breaks and doubled quotes.
+/// Blank lines are skipped.
+fn parse_csv(contents: &str) -> Result<Vec<Vec<String>>, DbError> {
+    let mut records: Vec<Vec<String>> = vec![];
+    let mut record: Vec<String> = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+
+    let mut end_record = |record: &mut Vec<String>, field: &mut String| {
+        record.push(std::mem::take(field));
+        let record = std::mem::take(record);
+        if !(record.len() == 1 && record[0].is_empty()) {
+            records.push(record);
+        }
+    };
+
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+        } else {
+            match c {
+                '"' => in_quotes = true,
+                ',' => record.push(std::mem::take(&mut field)),
+                '\r' => (),
+                '\n' => end_record(&mut record, &mut field),
+                _ => field.push(c),
+            }
+        }
+    }
+    if in_quotes {
+        return Err(DbError::Csv(String::from("unterminated quoted field")));
+    }
+    end_record(&mut record, &mut field);
+    Ok(records)
+}
+
+/// Serde helpers for `Data::DbBytes`. Human readable formats like JSON get a padded base64
+/// string, binary formats get the plain bytes.
+mod base64 {
+    use serde::de::{Deserializer, Error};
+    use serde::{Deserialize, Serializer};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            decode(&encoded).ok_or_else(|| D::Error::custom("invalid base64"))
+        } else {
+            Vec::<u8>::deserialize(deserializer)
+        }
+    }
+
+    // `div_ceil()` and `is_multiple_of()` are avoided to keep building on older compilers.
+    #[allow(clippy::manual_div_ceil)]
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+        for chunk in bytes.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+        let encoded = encoded.as_bytes();
+        if encoded.len() % 4 != 0 {
+            return None;
+        }
+        let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+        for (i, chunk) in encoded.chunks(4).enumerate() {
+            let last = i + 1 == encoded.len() / 4;
+            let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+            if padding > 2 || (padding > 0 && !last) {
+                return None;
+            }
+            let mut n = 0u32;
+            for &c in &chunk[..4 - padding] {
+                let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+                n = (n << 6) | value;
+            }
+            n <<= 6 * padding as u32;
+            let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+            bytes.extend_from_slice(&decoded[..3 - padding]);
+        }
+        Some(bytes)
+    }
+}
+
+mod tests {
+    #[cfg(test)]
+    use super::{
+        ChangeEvent, ConcurrentDb, Data, DataKind, Db, DbDiff, DbError, Entry, Predicate, Query,
+        QueryResult, RowBuilder, RowId, SchemaError, Upsert, CREATED_AT,
+    };
+    #[cfg(test)]
+    use chrono::NaiveDateTime;
+    #[cfg(test)]
+    use std::collections::HashMap;
+    #[cfg(all(test, feature = "flate2"))]
+    use std::fs::File;
+    #[cfg(all(test, feature = "flate2"))]
+    use std::io::Read;
+    #[cfg(test)]
+    use std::sync::{Arc, Mutex};
+    #[cfg(test)]
+    use std::thread;
+
+    #[test]
+    fn match_row() {
+        let db = new_db_with_entries("testdb");
+
+        let p1 = Predicate::new_equal_string("name", "coche");
+        let p2 = Predicate::new_starts_with("name", "co");
+        let p3 = Predicate::new_contains("name", "och");
+
+        println!("{:?}", db.debug_rows(&[RowId(2)]));
+        println!("{:?}", db.debug_rows(&[RowId(1)]));
+
+        assert!(db.match_row(RowId(2), &p1));
+        assert!(db.match_row(RowId(2), &p2));
+        assert!(db.match_row(RowId(2), &p3));
+
+        assert!(!db.match_row(RowId(1), &p1));
+        assert!(!db.match_row(RowId(1), &p2));
+        assert!(!db.match_row(RowId(1), &p3));
+    }
+
+    #[test]
+    fn starts_with_contains() {
+        let s1 = Db::db_string("hello");
+        let s2 = Db::db_string("hello world");
+        let s3 = Db::db_string("o wor");
+        assert!(s2.starts_with(&s1));
+        assert!(!s1.starts_with(&s2));
+        assert!(s2.contains(&s3));
+        assert!(!s3.contains(&s2));
+    }
+
+    #[test]
+    fn compare() {
+        let p1 = Predicate::new_equal_string("set", "es-en");
+        let p2 = Predicate::new_starts_with("set", "es");
+        let p3 = Predicate::new_contains("set", "s-e");
+
+        let e1 = Entry {
+            name: String::from("set"),
+            value: Db::db_string("es-en"),
+        };
+
+        let e2 = Entry {
+            name: String::from("set"),
+            value: Db::db_string("en-es"),
+        };
+
+        assert!(e1.compare(&p1));
+        assert!(!e2.compare(&p1));
+
+        assert!(e1.compare(&p2));
+        assert!(!e2.compare(&p2));
+
+        assert!(e1.compare(&p3));
+        assert!(!e2.compare(&p3));
+    }
+
+    #[cfg(test)]
+    fn new_db_with_entries(name: &str) -> Db {
+        let mut db = Db::new(name);
         let _id = db.add_row(vec![
             Entry {
-                name: String::from("set"),
-                value: Db::db_string("es-en"),
+                name: String::from("set"),
+                value: Db::db_string("es-en"),
+            },
+            Entry {
+                name: String::from("name"),
+                value: Db::db_string("disfrutar"),
+            },
+            Entry {
+                name: String::from("value"),
+                value: Db::db_string("to enjoy"),
+            },
+        ]);
+        let _id = db.add_row(vec![
+            Entry {
+                name: String::from("set"),
+                value: Db::db_string("es-en"),
+            },
+            Entry {
+                name: String::from("name"),
+                value: Db::db_string("coche"),
+            },
+            Entry {
+                name: String::from("value"),
+                value: Db::db_string("car"),
+            },
+        ]);
+        db
+    }
+
+    #[cfg(test)]
+    fn check_single_entries(db: &Db) {
+        assert_eq!(db.by_row_id.len(), 2);
+
+        for value in &db.by_value {
+            println!("{:?}", value);
+        }
+        let row_ids = db.find_row_ids_by_value("set", &Db::db_string("es-en"));
+        assert_eq!(row_ids.len(), 2);
+
+        let row_ids = db.find_row_ids_by_value("value", &Db::db_string("car"));
+        println!("find_row_ids_by_value(): {:?}", row_ids);
+        assert_eq!(row_ids.len(), 1);
+    }
+
+    #[test]
+    fn find_row_ids_by_predicate() {
+        let name = "testdb";
+        let db = new_db_with_entries(name);
+
+        let predicates1 = vec![Predicate::new_equal_string("set", "es-en")];
+        let predicates2 = vec![
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_equal_string("name", "disfrutar"),
+        ];
+
+        let row_ids = db.find_row_ids_by_predicate(&predicates1, None);
+        assert_eq!(row_ids, vec![RowId(1), RowId(2)]);
+
+        let row_ids = db.find_row_ids_by_predicate(&predicates2, None);
+        assert_eq!(row_ids, vec![RowId(1)]);
+    }
+
+    #[test]
+    fn find_entries_by_predicate() {
+        let name = "testdb";
+        let db = new_db_with_entries(name);
+
+        let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+
+        let result1 = vec![
+            vec![Entry {
+                name: String::from("name"),
+                value: Db::db_string("disfrutar"),
+            }],
+            vec![Entry {
+                name: String::from("name"),
+                value: Db::db_string("coche"),
+            }],
+        ];
+
+        let result2 = vec![
+            vec![
+                Entry {
+                    name: String::from("name"),
+                    value: Db::db_string("disfrutar"),
+                },
+                Entry {
+                    name: String::from("value"),
+                    value: Db::db_string("to enjoy"),
+                },
+            ],
+            vec![
+                Entry {
+                    name: String::from("name"),
+                    value: Db::db_string("coche"),
+                },
+                Entry {
+                    name: String::from("value"),
+                    value: Db::db_string("car"),
+                },
+            ],
+        ];
+
+        let result = db.find_entries_by_predicate(&predicates, &["name"]);
+        assert_eq!(result, result1);
+
+        let result = db.find_entries_by_predicate(&predicates, &["name", "value"]);
+        assert_eq!(result, result2);
+    }
+
+    #[test]
+    fn load_and_save() {
+        let name = "testdb";
+        let mut db = new_db_with_entries(name);
+        db.save().unwrap();
+        let db = Db::load(name).unwrap();
+        check_single_entries(&db);
+    }
+
+    #[test]
+    fn add_row() {
+        let db = new_db_with_entries("testdb");
+        check_single_entries(&db);
+    }
+
+    #[test]
+    fn data_types() {
+        let t = "Test";
+        assert_eq!(Data::DbString(String::from(t)), Db::db_string(t));
+        let t = 42;
+        assert_eq!(Data::DbI32(t), Db::db_i32(t));
+        let fmt = "%Y-%m-%d %H:%M:%S";
+        let t = "2013-11-22 12:00:00";
+        let dt = NaiveDateTime::parse_from_str(t, fmt).unwrap();
+        assert_eq!(Data::DbDateTime(dt), Db::db_datetime(t).unwrap());
+    }
+
+    #[test]
+    fn add_or_update_entry_add() {
+        let mut db = new_db_with_entries("testdb");
+
+        println!("Before update/add: {:?}", db.debug_rows(&[RowId(2)]));
+        db.add_or_update_entry(
+            RowId(2),
+            Entry {
+                name: String::from("new entry"),
+                value: Db::db_string("new entry content"),
+            },
+        );
+        println!("After update/add: {:?}", db.debug_rows(&[RowId(2)]));
+
+        for (i, row) in db.by_row_id.iter().enumerate() {
+            println!("row: {} {:?}", i, row);
+        }
+        assert_eq!(db.find_first_row_id_by_name("new entry"), Some(RowId(2)));
+        assert_eq!(
+            db.find_first_row_id_by_value("new entry", &Db::db_string("new entry content")),
+            Some(RowId(2))
+        );
+    }
+
+    #[test]
+    fn add_or_update_entry_update() {
+        let mut db = new_db_with_entries("testdb");
+
+        println!("{:?}", db.debug_rows(&[RowId(2)]));
+        db.add_or_update_entry(
+            RowId(2),
+            Entry {
+                name: String::from("new entry"),
+                value: Db::db_string("new entry content"),
+            },
+        );
+        db.add_or_update_entry(
+            RowId(2),
+            Entry {
+                name: String::from("new entry"),
+                value: Db::db_string("new entry content updated"),
+            },
+        );
+        println!("{:?}", db.debug_rows(&[RowId(2)]));
+        for (row_id, entries) in db.by_row_id.iter() {
+            println!("{:?}", row_id);
+            for entry in entries {
+                println!("    {:?}", entry);
+            }
+        }
+        assert_eq!(
+            db.find_first_row_id_by_value("new entry", &Db::db_string("new entry content updated")),
+            Some(RowId(2))
+        );
+    }
+
+    #[test]
+    fn delete_entry_all() {
+        let mut db = new_db_with_entries("testdb");
+
+        let mut add_row = |n| {
+            if let Some(_entry) = db.by_row_id.get(&RowId(n)) {
+                println!("{:?}", db.debug_rows(&[RowId(n)]));
+            }
+            db.add_row_id_entry(
+                RowId(n),
+                Entry {
+                    name: String::from("new entry"),
+                    value: Db::db_string(&format!("new col for row {}", n)),
+                },
+            );
+        };
+        add_row(1);
+        add_row(2);
+        add_row(3);
+        for (row_id, entries) in db.by_row_id.iter() {
+            println!("{:?}", row_id);
+            for entry in entries {
+                println!("    {:?}", entry);
+            }
+        }
+
+        let row_ids = db.find_row_ids_by_name("new entry");
+        assert_eq!(row_ids.len(), 3);
+        assert!(row_ids.contains(&RowId(1)));
+        assert!(row_ids.contains(&RowId(2)));
+        assert!(row_ids.contains(&RowId(3)));
+
+        println!("Deleting entries...");
+        db.delete_entry_all("new entry");
+
+        for (row_id, entries) in db.by_row_id.iter() {
+            println!("{:?}", row_id);
+            for entry in entries {
+                println!("    {:?}", entry);
+            }
+        }
+
+        let row_ids = db.find_row_ids_by_name("new entry");
+        assert_eq!(row_ids.len(), 0);
+
+        let row_ids = db.find_row_ids_by_name("set");
+        assert_eq!(row_ids.len(), 2);
+        assert!(row_ids.contains(&RowId(1)));
+        assert!(row_ids.contains(&RowId(2)));
+        assert!(!row_ids.contains(&RowId(3)));
+
+        let row_ids = db.find_row_ids_by_name("name");
+        assert_eq!(row_ids.len(), 2);
+        assert!(row_ids.contains(&RowId(1)));
+        assert!(row_ids.contains(&RowId(2)));
+        assert!(!row_ids.contains(&RowId(3)));
+    }
+
+    #[test]
+    fn find_all_row_ids() {
+        let db = new_db_with_entries("testdb");
+        let row_ids = db.find_all_row_ids();
+        assert_eq!(row_ids.len(), 2);
+        assert!(row_ids.contains(&RowId(1)));
+        assert!(row_ids.contains(&RowId(2)));
+    }
+
+    #[test]
+    fn find_row_ids_by_value() {
+        let db = new_db_with_entries("testdb");
+        let entry = Entry::new_string("name", "coche");
+        let row_id = db.by_value[&entry].iter().next().unwrap();
+        let entry_new = db.by_row_id[row_id]
+            .iter()
+            .find(|entry| &entry.name == "name")
+            .unwrap();
+        assert_eq!(&entry, entry_new);
+    }
+
+    #[test]
+    fn import_csv() {
+        let mut db = Db::new("testdb");
+        let csv = "name,value,set\n\
+                   coche,car,es-en\n\
+                   \n\
+                   \"cocina\",\"kitchen, cuisine\",es-en\n\
+                   \"dicho\",\"\"\"saying\"\"\"\n";
+        let row_ids = db.import_csv(csv.as_bytes(), true).unwrap();
+        assert_eq!(row_ids, vec![RowId(1), RowId(2), RowId(3)]);
+
+        let entries = db.entries_from_row_ids(&row_ids, &["name", "value", "set"]);
+        assert_eq!(
+            entries[1],
+            vec![
+                Entry::new_string("name", "cocina"),
+                Entry::new_string("value", "kitchen, cuisine"),
+                Entry::new_string("set", "es-en"),
+            ]
+        );
+        // Ragged row: the missing "set" column is not added
+        assert_eq!(
+            entries[2],
+            vec![
+                Entry::new_string("name", "dicho"),
+                Entry::new_string("value", "\"saying\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_csv_without_header() {
+        let mut db = Db::new("testdb");
+        let row_ids = db
+            .import_csv("coche,car\r\nmoto,motorbike\r\n".as_bytes(), false)
+            .unwrap();
+        assert_eq!(row_ids.len(), 2);
+        assert_eq!(
+            db.find_first_row_id_by_value("col1", &Db::db_string("motorbike")),
+            Some(row_ids[1])
+        );
+        assert!(db.import_csv("\"coche,car".as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn count_by_predicate() {
+        let db = new_db_with_entries("testdb");
+        let queries = vec![
+            vec![],
+            vec![Predicate::new_equal_string("set", "es-en")],
+            vec![Predicate::new_equal_string("set", "en-es")],
+            vec![
+                Predicate::new_equal_string("set", "es-en"),
+                Predicate::new_equal_string("name", "coche"),
+            ],
+            vec![Predicate::new_starts_with("name", "d")],
+            vec![
+                Predicate::new_contains("value", "o"),
+                Predicate::new_equal_string("set", "es-en"),
+            ],
+            vec![Predicate::new_any_string("value")],
+        ];
+        for predicates in &queries {
+            assert_eq!(
+                db.count_by_predicate(predicates),
+                db.find_row_ids_by_predicate(predicates, None).len()
+            );
+        }
+    }
+
+    #[test]
+    fn sort_row_ids_by() {
+        let mut db = Db::new("testdb");
+        let date = Db::db_datetime("2018-01-01 12:00:00").unwrap();
+        let r1 = db.add_row(vec![Entry::new_string("sort", "b")]);
+        let r2 = db.add_row(vec![Entry::new_i32("sort", 10)]);
+        let r3 = db.add_row(vec![Entry::new_string("other", "x")]);
+        let r4 = db.add_row(vec![Entry {
+            name: String::from("sort"),
+            value: date,
+        }]);
+        let r5 = db.add_row(vec![Entry::new_string("sort", "a")]);
+        let r6 = db.add_row(vec![Entry::new_i32("sort", -3)]);
+
+        let mut row_ids = vec![r1, r2, r3, r4, r5, r6];
+        db.sort_row_ids_by(&mut row_ids, "sort", false);
+        assert_eq!(row_ids, vec![r6, r2, r5, r1, r4, r3]);
+
+        db.sort_row_ids_by(&mut row_ids, "sort", true);
+        assert_eq!(row_ids, vec![r4, r1, r5, r2, r6, r3]);
+    }
+
+    #[test]
+    fn find_row_ids_by_predicate_paged() {
+        let mut db = new_db_with_entries("testdb");
+        for i in 0..5 {
+            db.add_row(vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_string("name", &format!("word {}", i)),
+            ]);
+        }
+        let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+        let all = db.find_row_ids_by_predicate(&predicates, None);
+        assert_eq!(all.len(), 7);
+
+        let page1 = db.find_row_ids_by_predicate_paged(&predicates, 0, 4);
+        let page2 = db.find_row_ids_by_predicate_paged(&predicates, 4, 4);
+        assert_eq!(page1.len(), 4);
+        assert_eq!(page2.len(), 3);
+        assert!(page1.iter().all(|row_id| !page2.contains(row_id)));
+        assert_eq!([page1, page2].concat(), all);
+
+        assert!(db
+            .find_row_ids_by_predicate_paged(&predicates, 7, 4)
+            .is_empty());
+        assert!(db
+            .find_row_ids_by_predicate_paged(&predicates, 0, 0)
+            .is_empty());
+        assert_eq!(db.find_row_ids_by_predicate_paged(&[], 0, 10).len(), 7);
+    }
+
+    #[test]
+    fn distinct_values() {
+        let mut db = new_db_with_entries("testdb");
+        let row_id = db.add_row(vec![Entry::new_string("set", "de-en")]);
+        db.add_row(vec![Entry::new_string("set", "es-en")]);
+        db.add_row(vec![Entry::new_string("set", "fr-en")]);
+        db.delete_rows(&[row_id]);
+
+        assert_eq!(
+            db.distinct_values("set"),
+            vec![Db::db_string("es-en"), Db::db_string("fr-en")]
+        );
+        assert!(db.distinct_values("missing").is_empty());
+    }
+
+    #[test]
+    fn count_by_value() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row(vec![
+            Entry::new_string("set", "fr-en"),
+            Entry::new_string("set", "fr-en"),
+            Entry::new_string("name", "voiture"),
+        ]);
+        let counts = db.count_by_value("set");
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&Db::db_string("es-en")], 2);
+        assert_eq!(counts[&Db::db_string("fr-en")], 1);
+        assert!(db.count_by_value("missing").is_empty());
+    }
+
+    #[test]
+    fn sum_and_avg_i32() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.sum_i32("add_counter"), 0);
+        assert_eq!(db.avg_i32("add_counter"), None);
+
+        db.add_row(vec![
+            Entry::new_i32("add_counter", i32::MAX),
+            Entry::new_i32("add_counter", 1),
+        ]);
+        db.add_row(vec![Entry::new_i32("add_counter", i32::MAX)]);
+        db.add_row(vec![Entry::new_string("add_counter", "many")]);
+        assert_eq!(db.sum_i32("add_counter"), 2 * i64::from(i32::MAX) + 1);
+        assert_eq!(
+            db.avg_i32("add_counter"),
+            Some((2 * i64::from(i32::MAX) + 1) as f64 / 3.0)
+        );
+
+        db.add_row(vec![Entry::new_string("only_strings", "1")]);
+        assert_eq!(db.sum_i32("only_strings"), 0);
+        assert_eq!(db.avg_i32("only_strings"), None);
+    }
+
+    #[test]
+    fn min_and_max() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.min_i32("add_counter"), None);
+        assert_eq!(db.max_i32("add_counter"), None);
+        assert_eq!(db.min_datetime("add_date"), None);
+        assert_eq!(db.max_datetime("add_date"), None);
+
+        let early = Db::db_datetime("2017-12-25 08:00:00").unwrap();
+        let late = Db::db_datetime("2018-01-01 00:00:00").unwrap();
+        for (counter, date) in &[(5, &late), (-2, &early), (5, &early), (-2, &late)] {
+            db.add_row(vec![
+                Entry::new_i32("add_counter", *counter),
+                Entry {
+                    name: String::from("add_date"),
+                    value: (*date).clone(),
+                },
+            ]);
+        }
+        db.add_row(vec![
+            Entry::new_string("add_counter", "100"),
+            Entry::new_string("add_date", "2019-01-01 00:00:00"),
+        ]);
+
+        assert_eq!(db.min_i32("add_counter"), Some(-2));
+        assert_eq!(db.max_i32("add_counter"), Some(5));
+        assert_eq!(
+            db.min_datetime("add_date").map(Data::DbDateTime),
+            Some(early)
+        );
+        assert_eq!(
+            db.max_datetime("add_date").map(Data::DbDateTime),
+            Some(late)
+        );
+    }
+
+    #[test]
+    fn index_lookups_match_scan() {
+        let mut db = Db::new("testdb");
+        for i in 0..200 {
+            db.add_row(vec![
+                Entry::new_string("name", &format!("word {}", i % 17)),
+                Entry::new_i32("number", i % 5),
+            ]);
+        }
+        db.delete_rows(&[RowId(3), RowId(50), RowId(199)]);
+        db.remove_by_name(RowId(7), "name");
+
+        let scan = |name: &str, value: &Data| {
+            let mut row_ids = db
+                .by_row_id
+                .iter()
+                .filter(|(_row_id, entries)| Entry::check_by_value(entries, name, value))
+                .map(|(row_id, _entries)| *row_id)
+                .collect::<Vec<RowId>>();
+            row_ids.sort();
+            row_ids
+        };
+
+        for i in 0..17 {
+            let value = Db::db_string(&format!("word {}", i));
+            let mut row_ids = db.find_row_ids_by_value("name", &value);
+            row_ids.sort();
+            assert_eq!(row_ids, scan("name", &value));
+        }
+        for i in 0..5 {
+            let predicates = vec![Predicate::new_equal_i32("number", i)];
+            let row_ids = db.find_row_ids_by_predicate(&predicates, None);
+            assert_eq!(row_ids, scan("number", &Db::db_i32(i)));
+        }
+    }
+
+    #[test]
+    fn iter_rows() {
+        let db = new_db_with_entries("testdb");
+        let mut row_ids = db
+            .iter_rows()
+            .map(|(row_id, _entries)| row_id)
+            .collect::<Vec<RowId>>();
+        let mut all_row_ids = db.find_all_row_ids();
+        row_ids.sort();
+        all_row_ids.sort();
+        assert_eq!(row_ids, all_row_ids);
+
+        for (row_id, entries) in db.iter_rows() {
+            assert_eq!(entries, db.debug_rows(&[row_id])[0].as_slice());
+        }
+    }
+
+    #[test]
+    fn add_rows() {
+        let mut db = new_db_with_entries("testdb");
+        let rows = (0..100)
+            .map(|i| {
+                vec![
+                    Entry::new_string("set", "es-en"),
+                    Entry::new_i32("number", i),
+                ]
+            })
+            .collect::<Vec<Vec<Entry>>>();
+        let row_ids = db.add_rows(rows);
+        assert_eq!(row_ids, (3..103).map(RowId).collect::<Vec<RowId>>());
+        assert_eq!(db.add_row(vec![]), RowId(103));
+
+        for (i, row_id) in row_ids.iter().enumerate() {
+            let predicates = vec![Predicate::new_equal_i32("number", i as i32)];
+            assert_eq!(db.find_row_ids_by_predicate(&predicates, None), [*row_id]);
+        }
+        assert_eq!(
+            db.find_row_ids_by_value("set", &Db::db_string("es-en"))
+                .len(),
+            102
+        );
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let mut db = new_db_with_entries("testdb");
+        let before = db.clone();
+
+        let mut transaction = db.begin();
+        transaction.add_row(vec![Entry::new_string("name", "moto")]);
+        transaction.add_row(vec![
+            Entry::new_string("set", "es-en"),
+            Entry::new_string("name", "bici"),
+        ]);
+        assert_eq!(transaction.db().find_all_row_ids().len(), 4);
+        transaction.rollback();
+        assert_eq!(db, before);
+        assert_eq!(db.by_name, before.by_name);
+        assert_eq!(db.by_value, before.by_value);
+        assert_eq!(db.add_row(vec![]), RowId(3));
+
+        let mut db = before.clone();
+        {
+            let mut transaction = db.begin();
+            transaction.add_row(vec![Entry::new_string("name", "moto")]);
+        }
+        assert_eq!(db, before);
+    }
+
+    #[test]
+    fn transaction_rollback_keeps_existing_index_keys() {
+        let mut db = new_db_with_entries("testdb");
+        db.delete_rows(&[RowId(2)]);
+        let before = db.clone();
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&events);
+        db.on_change(Box::new(move |event| log.lock().unwrap().push(event)));
+        let mut transaction = db.begin();
+        transaction.add_row(vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_string("plural", "coches"),
+        ]);
+        transaction.rollback();
+        assert_eq!(db, before);
+        assert_eq!(db.by_name, before.by_name);
+        assert_eq!(db.by_value, before.by_value);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ChangeEvent::RowAdded(RowId(3)),
+                ChangeEvent::RowDeleted(RowId(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn transaction_commit() {
+        let mut db = new_db_with_entries("testdb");
+        let mut transaction = db.begin();
+        let row_id = transaction.add_row(vec![Entry::new_string("name", "moto")]);
+        transaction.commit();
+        assert_eq!(
+            db.find_first_row_id_by_value("name", &Db::db_string("moto")),
+            Some(row_id)
+        );
+    }
+
+    #[test]
+    fn last_n_rows() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row(vec![Entry::new_string("name", "moto")]);
+        db.add_row(vec![Entry::new_string("name", "bici")]);
+        db.delete_rows(&[RowId(3)]);
+
+        assert_eq!(db.last_n_rows(2), vec![RowId(4), RowId(2)]);
+        assert_eq!(db.last_n_rows(10), vec![RowId(4), RowId(2), RowId(1)]);
+        assert!(db.last_n_rows(0).is_empty());
+    }
+
+    #[test]
+    fn get_name() {
+        assert_eq!(Db::new("testdb").get_name(), "testdb");
+        assert_eq!(Db::new("x").get_name(), "x");
+        assert_eq!(Db::new("deep/path/to/db").get_name(), "deep/path/to/db");
+
+        let mut db = Db::new("testdb");
+        db.full_filename = String::from("/var/lib/vdb/vocabulary");
+        assert_eq!(db.get_name(), "vocabulary");
+        db.full_filename = String::new();
+        assert_eq!(db.get_name(), "");
+    }
+
+    #[test]
+    fn db_error_kinds() {
+        match Db::load("does-not-exist") {
+            Err(DbError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("Expected DbError::Io, got {:?}", other),
+        }
+        match Db::db_datetime("2013-11-22") {
+            Err(DbError::DateTimeParse(_)) => (),
+            other => panic!("Expected DbError::DateTimeParse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn save_returns_errors() {
+        let mut db = new_db_with_entries("missing-dir/testdb");
+        match db.save() {
+            Err(DbError::Io(_)) => (),
+            other => panic!("Expected DbError::Io, got {:?}", other),
+        }
+
+        let mut db = new_db_with_entries("testdb-save");
+        assert!(db.save().is_ok());
+        check_single_entries(&Db::load("testdb-save").unwrap());
+    }
+
+    #[test]
+    fn find_first_datetime() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.find_first_datetime("add_date"), None);
+        assert_eq!(db.find_first_datetime("name"), None);
+
+        let t = "2013-11-22 12:00:00";
+        let dt = NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").unwrap();
+        db.add_row(vec![Entry {
+            name: String::from("add_date"),
+            value: Db::db_datetime(t).unwrap(),
+        }]);
+        assert_eq!(db.find_first_datetime("add_date"), Some(dt));
+    }
+
+    #[test]
+    fn typed_getters_by_row_id() {
+        let mut db = Db::new("testdb");
+        let t = "2013-11-22 12:00:00";
+        let dt = NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").unwrap();
+        let row_id = db.add_row(vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_i32("add_counter", 3),
+            Entry {
+                name: String::from("add_date"),
+                value: Db::db_datetime(t).unwrap(),
             },
+        ]);
+        let other = db.add_row(vec![Entry::new_string("name", "moto")]);
+
+        assert_eq!(db.get_string(row_id, "name"), Some(String::from("coche")));
+        assert_eq!(db.get_i32(row_id, "add_counter"), Some(3));
+        assert_eq!(db.get_datetime(row_id, "add_date"), Some(dt));
+
+        assert_eq!(db.get_i32(other, "add_counter"), None);
+        assert_eq!(db.get_datetime(other, "add_date"), None);
+        assert_eq!(db.get_string(RowId(99), "name"), None);
+
+        assert_eq!(db.get_i32(row_id, "name"), None);
+        assert_eq!(db.get_string(row_id, "add_counter"), None);
+        assert_eq!(db.get_datetime(row_id, "name"), None);
+    }
+
+    #[test]
+    fn delete_rows_count() {
+        let mut db = new_db_with_entries("testdb");
+        let row_id = db.add_row(vec![Entry::new_string("name", "moto")]);
+        assert_eq!(db.delete_rows(&[RowId(1)]), 1);
+        assert_eq!(db.delete_rows(&[RowId(1), row_id, RowId(99), row_id]), 1);
+        assert_eq!(db.delete_rows(&[]), 0);
+        assert_eq!(db.find_all_row_ids(), vec![RowId(2)]);
+    }
+
+    #[test]
+    fn update_value() {
+        let mut db = new_db_with_entries("testdb");
+        assert!(db.update_value(RowId(2), "value", Db::db_string("automobile")));
+        assert_eq!(
+            db.find_first_row_id_by_value("value", &Db::db_string("car")),
+            None
+        );
+        assert_eq!(
+            db.find_first_row_id_by_value("value", &Db::db_string("automobile")),
+            Some(RowId(2))
+        );
+        assert_eq!(db.find_row_ids_by_name("value").len(), 2);
+
+        assert!(!db.update_value(RowId(2), "missing", Db::db_string("x")));
+        assert!(!db.update_value(RowId(99), "value", Db::db_string("x")));
+
+        let row_id = db.add_row(vec![
+            Entry::new_string("value", "same"),
+            Entry::new_string("value", "same"),
+        ]);
+        assert!(db.update_value(row_id, "value", Db::db_string("changed")));
+        assert_eq!(
+            db.find_first_row_id_by_value("value", &Db::db_string("same")),
+            Some(row_id)
+        );
+        assert_eq!(
+            db.find_first_row_id_by_value("value", &Db::db_string("changed")),
+            Some(row_id)
+        );
+    }
+
+    #[test]
+    fn add_or_update_entry_result() {
+        let mut db = new_db_with_entries("testdb");
+        let new_entry = Entry::new_string("new entry", "new entry content");
+        assert_eq!(
+            db.add_or_update_entry(RowId(2), new_entry.clone()),
+            Upsert::Inserted
+        );
+        assert_eq!(
+            db.add_or_update_entry(RowId(2), new_entry.clone()),
+            Upsert::Updated(1)
+        );
+
+        db.add_row_id_entry(RowId(1), Entry::new_string("value", "to like"));
+        assert_eq!(
+            db.add_or_update_entry(RowId(1), Entry::new_string("value", "to enjoy")),
+            Upsert::Updated(2)
+        );
+        assert_eq!(db.find_row_ids_by_name("value").len(), 2);
+    }
+
+    #[test]
+    fn in_strings_predicate() {
+        let db = new_db_with_entries("testdb");
+        let p = Predicate::new_in_strings("name", &["coche", "moto"]);
+        assert!(db.match_row(RowId(2), &p));
+        assert!(!db.match_row(RowId(1), &p));
+        assert_eq!(db.find_row_ids_by_predicate(&[p], None), vec![RowId(2)]);
+
+        let predicates = vec![
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_in_strings("name", &["disfrutar", "moto"]),
+        ];
+        assert_eq!(
+            db.find_row_ids_by_predicate(&predicates, None),
+            vec![RowId(1)]
+        );
+        assert_eq!(db.count_by_predicate(&predicates), 1);
+
+        let p = Predicate::new_in_strings("name", &["moto", "bici"]);
+        assert!(db.find_row_ids_by_predicate(&[p], None).is_empty());
+    }
+
+    #[test]
+    fn shared_db_threads() {
+        let shared = new_db_with_entries("testdb").into_shared();
+        let handles = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+                    let row_ids = shared.find_row_ids_by_predicate(&predicates, None);
+                    shared.entries_from_row_ids(&row_ids, &["name"])
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let entries = handle.join().unwrap();
+            assert_eq!(entries[1], vec![Entry::new_string("name", "coche")]);
+        }
+
+        let mut db = shared.into_db();
+        db.add_row(vec![Entry::new_string("name", "moto")]);
+        assert_eq!(db.find_all_row_ids().len(), 3);
+    }
+
+    #[test]
+    fn concurrent_db_threads() {
+        let db = Arc::new(ConcurrentDb::new(new_db_with_entries("testdb")));
+        let handles = (0..8)
+            .map(|i| {
+                let db = Arc::clone(&db);
+                thread::spawn(move || {
+                    for j in 0..50 {
+                        db.add_row(vec![
+                            Entry::new_string("set", "es-en"),
+                            Entry::new_i32("thread", i),
+                            Entry::new_i32("number", j),
+                        ]);
+                        let predicates = vec![Predicate::new_equal_i32("thread", i)];
+                        let row_ids = db.find_row_ids_by_predicate(&predicates, None);
+                        assert_eq!(row_ids.len(), j as usize + 1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+        assert_eq!(db.find_row_ids_by_predicate(&predicates, None).len(), 402);
+
+        // A panic while holding the lock poisons it, the db must stay usable
+        let poisoner = Arc::clone(&db);
+        let _ = thread::spawn(move || poisoner.write(|_db| panic!("poison"))).join();
+        assert_eq!(db.read(|db| db.count_by_predicate(&predicates)), 402);
+        db.add_row(vec![Entry::new_string("set", "es-en")]);
+        assert_eq!(db.read(|db| db.count_by_predicate(&predicates)), 403);
+    }
+
+    #[test]
+    fn prefix_index() {
+        let mut db = new_db_with_entries("testdb");
+        for word in &["cocina", "coche", "cocer", "barco", "co", "c", "cocinar"] {
+            db.add_row(vec![Entry::new_string("name", word)]);
+        }
+        db.add_row(vec![Entry::new_string("other", "cocina")]);
+        db.add_row(vec![Entry::new_i32("name", 5)]);
+        let mut indexed = db.clone();
+        indexed.build_prefix_index();
+        assert_eq!(indexed, db);
+
+        indexed.add_row(vec![Entry::new_string("name", "coco")]);
+        db.add_row(vec![Entry::new_string("name", "coco")]);
+        indexed.delete_rows(&[RowId(3)]);
+        db.delete_rows(&[RowId(3)]);
+        indexed.add_or_update_entry(RowId(4), Entry::new_string("name", "cola"));
+        db.add_or_update_entry(RowId(4), Entry::new_string("name", "cola"));
+        indexed.update_value(RowId(6), "name", Db::db_string("cocido"));
+        db.update_value(RowId(6), "name", Db::db_string("cocido"));
+
+        for prefix in &["", "c", "co", "coc", "coci", "cocina", "x", "cocinar!"] {
+            let predicates = vec![Predicate::new_starts_with("name", prefix)];
+            let expected = db.find_row_ids_by_predicate(&predicates, None);
+            assert_eq!(
+                indexed.find_row_ids_by_predicate(&predicates, None),
+                expected,
+                "prefix {:?}",
+                prefix
+            );
+        }
+        assert_eq!(
+            indexed
+                .find_row_ids_by_predicate(&[Predicate::new_starts_with("name", "coci")], None)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn load_keeps_row_ids() {
+        let mut db = new_db_with_entries("testdb-row-ids");
+        db.add_row(vec![Entry::new_string("name", "moto")]);
+        db.delete_rows(&[RowId(2)]);
+        db.save().unwrap();
+        let mut loaded = Db::load("testdb-row-ids").unwrap();
+        assert_eq!(loaded.by_row_id, db.by_row_id);
+        assert_eq!(loaded.add_row(vec![]), RowId(4));
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn save_compressed() {
+        let mut db = new_db_with_entries("testdb-compressed");
+        db.add_row(vec![Entry::new_i32("add_counter", 3)]);
+        db.save_compressed().unwrap();
+
+        let mut contents = vec![];
+        File::open("save/testdb-compressed")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents[..2], [0x1f, 0x8b]);
+
+        assert_eq!(Db::load_compressed("testdb-compressed").unwrap(), db);
+        assert_eq!(Db::load("testdb-compressed").unwrap(), db);
+        assert_eq!(Db::load_streaming("testdb-compressed").unwrap(), db);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn save_binary() {
+        let mut db = new_db_with_entries("testdb-binary");
+        db.add_row(vec![
+            Entry::new_i32("add_counter", 3),
             Entry {
-                name: String::from("name"),
-                value: Db::db_string("coche"),
+                name: String::from("add_date"),
+                value: Db::db_datetime("2013-11-22 12:00:00").unwrap(),
             },
+        ]);
+        db.save_binary().unwrap();
+        let binary = Db::load_binary("testdb-binary").unwrap();
+
+        let mut json = db.clone();
+        json.full_filename = String::from("save/testdb-binary-json");
+        json.save().unwrap();
+        let json = Db::load("testdb-binary-json").unwrap();
+
+        assert_eq!(binary, db);
+        assert_eq!(binary.by_row_id, json.by_row_id);
+        assert_eq!(binary.by_value, json.by_value);
+    }
+
+    #[test]
+    fn validate_schema() {
+        let mut db = new_db_with_entries("testdb");
+        let spec = [("name", DataKind::String), ("add_counter", DataKind::I32)];
+        assert_eq!(db.validate_schema(&spec), Ok(()));
+
+        db.add_row(vec![Entry::new_i32("add_counter", 1)]);
+        db.add_row_id_entry(RowId(2), Entry::new_string("add_counter", "2"));
+        db.add_row_id_entry(RowId(1), Entry::new_i32("name", 7));
+        assert_eq!(
+            db.validate_schema(&spec),
+            Err(vec![
+                SchemaError {
+                    row_id: RowId(1),
+                    name: String::from("name"),
+                    expected: DataKind::String,
+                    found: DataKind::I32,
+                },
+                SchemaError {
+                    row_id: RowId(2),
+                    name: String::from("add_counter"),
+                    expected: DataKind::I32,
+                    found: DataKind::String,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn load_streaming() {
+        let mut db = new_db_with_entries("testdb-streaming");
+        db.add_row(vec![
+            Entry::new_i32("add_counter", 3),
             Entry {
-                name: String::from("value"),
-                value: Db::db_string("car"),
+                name: String::from("add_date"),
+                value: Db::db_datetime("2013-11-22 12:00:00").unwrap(),
             },
         ]);
-        db
+        db.save().unwrap();
+        let streamed = Db::load_streaming("testdb-streaming").unwrap();
+        assert_eq!(streamed, Db::load("testdb-streaming").unwrap());
+        assert_eq!(streamed, db);
+        match Db::load_streaming("does-not-exist") {
+            Err(DbError::Io(_)) => (),
+            other => panic!("Expected DbError::Io, got {:?}", other),
+        }
+    }
+
+    #[cfg(not(feature = "flate2"))]
+    #[test]
+    fn load_compressed_without_flate2() {
+        std::fs::write("save/testdb-gzip-disabled", [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        for loaded in &[
+            Db::load("testdb-gzip-disabled"),
+            Db::load_streaming("testdb-gzip-disabled"),
+        ] {
+            match loaded {
+                Err(DbError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+                other => panic!("Expected DbError::Io, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn row_and_entry_count() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.row_count(), 2);
+        assert_eq!(db.entry_count(), 6);
+        db.add_row(vec![Entry::new_string("name", "moto")]);
+        db.remove_by_name(RowId(1), "value");
+        assert_eq!(db.row_count(), 3);
+        assert_eq!(db.entry_count(), 6);
+    }
+
+    #[test]
+    fn clear() {
+        let mut db = new_db_with_entries("testdb");
+        db.build_prefix_index();
+        db.clear();
+        assert!(db.find_all_row_ids().is_empty());
+        assert!(db.find_row_ids_by_name("name").is_empty());
+        assert_eq!(db.get_name(), "testdb");
+
+        assert_eq!(
+            db.add_row(vec![Entry::new_string("name", "moto")]),
+            RowId(1)
+        );
+        let predicates = vec![Predicate::new_starts_with("name", "mo")];
+        assert_eq!(db.find_row_ids_by_predicate(&predicates, None), [RowId(1)]);
+        assert_eq!(db.distinct_values("name"), [Db::db_string("moto")]);
+    }
+
+    #[test]
+    fn merge() {
+        let mut db = new_db_with_entries("testdb");
+        let mut other = Db::new("testdb-fr");
+        other.add_row(vec![
+            Entry::new_string("set", "fr-en"),
+            Entry::new_string("name", "voiture"),
+        ]);
+        other.add_row(vec![
+            Entry::new_string("set", "fr-en"),
+            Entry::new_string("name", "cuisine"),
+        ]);
+        other.delete_rows(&[RowId(1)]);
+        other.add_row(vec![
+            Entry::new_string("set", "fr-en"),
+            Entry::new_string("name", "moto"),
+        ]);
+
+        let row_ids = db.merge(&other);
+        assert_eq!(row_ids, vec![RowId(3), RowId(4)]);
+        assert_eq!(db.row_count(), 4);
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[Predicate::new_equal_string("set", "es-en")], None),
+            vec![RowId(1), RowId(2)]
+        );
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[Predicate::new_equal_string("set", "fr-en")], None),
+            row_ids
+        );
+        assert_eq!(
+            db.get_string(RowId(3), "name"),
+            Some(String::from("cuisine"))
+        );
+        assert_eq!(other.row_count(), 2);
+    }
+
+    #[test]
+    fn entries_from_row_ids_order() {
+        let mut db = new_db_with_entries("testdb");
+        let row_id = db.add_row(vec![
+            Entry::new_string("value", "kitchen"),
+            Entry::new_string("name", "cocina"),
+            Entry::new_string("value", "cuisine"),
+            Entry::new_string("value", "cooker"),
+        ]);
+        let expected = vec![
+            vec![
+                Entry::new_string("name", "cocina"),
+                Entry::new_string("value", "kitchen"),
+                Entry::new_string("value", "cuisine"),
+                Entry::new_string("value", "cooker"),
+            ],
+            vec![
+                Entry::new_string("name", "coche"),
+                Entry::new_string("value", "car"),
+            ],
+        ];
+        for _ in 0..10 {
+            let entries = db.entries_from_row_ids(&[row_id, RowId(2)], &["name", "value"]);
+            assert_eq!(entries, expected);
+        }
+        let entries = db.entries_from_row_ids(&[RowId(2), row_id], &["value", "name"]);
+        assert_eq!(entries[0][0], Entry::new_string("value", "car"));
+        assert_eq!(entries[1][3], Entry::new_string("name", "cocina"));
+    }
+
+    #[test]
+    fn new_datetime_entries() {
+        let t = "2013-11-22 12:00:00";
+        let dt = NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").unwrap();
+        let entry = Entry::new_datetime("add_date", dt);
+        assert_eq!(entry.value, Data::DbDateTime(dt));
+        assert_eq!(Entry::new_datetime_str("add_date", t).unwrap(), entry);
+        match Entry::new_datetime_str("add_date", "22.11.2013") {
+            Err(DbError::DateTimeParse(_)) => (),
+            other => panic!("Expected DbError::DateTimeParse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn row_builder() {
+        let mut db = Db::new("testdb");
+        let dt = NaiveDateTime::parse_from_str("2013-11-22 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let builder = RowBuilder::new()
+            .string("name", "coche")
+            .i32("add_counter", 1)
+            .datetime("add_date", dt);
+        assert_eq!(
+            builder.clone().build(),
+            vec![
+                Entry::new_string("name", "coche"),
+                Entry::new_i32("add_counter", 1),
+                Entry::new_datetime("add_date", dt),
+            ]
+        );
+        let row_id = db.add_row_builder(builder);
+        assert_eq!(db.get_string(row_id, "name"), Some(String::from("coche")));
+        assert_eq!(db.get_i32(row_id, "add_counter"), Some(1));
+        assert_eq!(db.get_datetime(row_id, "add_date"), Some(dt));
+    }
+
+    #[test]
+    fn entries_from_row_ids_skips_missing() {
+        let mut db = new_db_with_entries("testdb");
+        db.delete_rows(&[RowId(1)]);
+        let entries = db.entries_from_row_ids(&[RowId(1), RowId(2), RowId(99)], &["name"]);
+        assert_eq!(entries, vec![vec![Entry::new_string("name", "coche")]]);
+    }
+
+    #[test]
+    fn find_first_entry_by_name_unknown_row() {
+        let db = new_db_with_entries("testdb");
+        assert_eq!(db.find_first_entry_by_name(RowId(99), "name"), None);
+        assert_eq!(
+            db.find_first_entry_by_name(RowId(2), "name"),
+            Some(Entry::new_string("name", "coche"))
+        );
+    }
+
+    #[test]
+    fn entries_map_from_row_ids() {
+        let db = new_db_with_entries("testdb");
+        let map = db.entries_map_from_row_ids(&[RowId(2), RowId(1), RowId(99)], &["value", "name"]);
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map[&RowId(1)],
+            vec![
+                Entry::new_string("value", "to enjoy"),
+                Entry::new_string("name", "disfrutar"),
+            ]
+        );
+        assert_eq!(
+            map[&RowId(2)],
+            vec![
+                Entry::new_string("value", "car"),
+                Entry::new_string("name", "coche"),
+            ]
+        );
+    }
+
+    #[test]
+    fn predicate_any_matches_all_types() {
+        let mut db = Db::new("testdb");
+        let dt = NaiveDateTime::parse_from_str("2013-11-22 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let row_i32 = db.add_row(vec![Entry::new_i32("value", 1)]);
+        let row_datetime = db.add_row(vec![Entry::new_datetime("value", dt)]);
+        let row_string = db.add_row(vec![Entry::new_string("value", "one")]);
+        let _other = db.add_row(vec![Entry::new_i32("other", 1)]);
+
+        let row_ids = db.find_row_ids_by_predicate(&[Predicate::new_any("value")], None);
+        assert_eq!(row_ids, vec![row_i32, row_datetime, row_string]);
+        let row_ids = db.find_row_ids_by_predicate(&[Predicate::new_any_string("value")], None);
+        assert_eq!(row_ids, vec![row_i32, row_datetime, row_string]);
+    }
+
+    #[test]
+    fn add_row_unique() {
+        let mut db = new_db_with_entries("testdb");
+        let row = vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_string("set", "es-en"),
+            Entry::new_string("value", "car"),
+        ];
+        assert_eq!(db.add_row_unique(row), None);
+        assert_eq!(db.row_count(), 2);
+
+        let row = vec![
+            Entry::new_string("set", "es-en"),
+            Entry::new_string("name", "coche"),
+        ];
+        assert_eq!(db.add_row_unique(row.clone()), Some(RowId(3)));
+        assert_eq!(db.add_row_unique(row), None);
+        assert_eq!(db.row_count(), 3);
+    }
+
+    #[test]
+    fn add_row_unique_with_timestamps() {
+        let mut db = new_db_with_entries("testdb");
+        db.enable_timestamps();
+        let row = vec![Entry::new_string("name", "moto")];
+        assert_eq!(db.add_row_unique(row.clone()), Some(RowId(3)));
+        assert!(db.get_datetime(RowId(3), CREATED_AT).is_some());
+        assert_eq!(db.add_row_unique(row), None);
+        assert_eq!(db.add_row_unique(vec![]), Some(RowId(4)));
+        assert_eq!(db.add_row_unique(vec![]), None);
+        assert_eq!(db.row_count(), 4);
+    }
+
+    #[test]
+    fn append_value() {
+        let mut db = new_db_with_entries("testdb");
+        assert!(!db.append_value(RowId(2), "value", Db::db_string("car")));
+        assert!(db.append_value(RowId(2), "value", Db::db_string("automobile")));
+        assert!(!db.append_value(RowId(2), "value", Db::db_string("automobile")));
+        assert_eq!(
+            db.entries_from_row_ids(&[RowId(2)], &["value"]),
+            vec![vec![
+                Entry::new_string("value", "car"),
+                Entry::new_string("value", "automobile"),
+            ]]
+        );
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("automobile")),
+            vec![RowId(2)]
+        );
+    }
+
+    #[test]
+    fn iter_by_predicate() {
+        let mut db = Db::new("testdb");
+        for word in &["coche", "moto", "bici", "barco"] {
+            db.add_row(vec![
+                Entry::new_string("word", word),
+                Entry::new_string("set", "es-en"),
+            ]);
+        }
+        for predicate in &[
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_contains("word", "o"),
+        ] {
+            let eager = db.find_by_predicate(predicate);
+            let lazy = db
+                .iter_by_predicate(predicate)
+                .take(2)
+                .collect::<Vec<RowId>>();
+            assert_eq!(lazy, eager[..2]);
+        }
+        let predicate = Predicate::new_equal_string("set", "en-es");
+        assert_eq!(db.iter_by_predicate(&predicate).next(), None);
+    }
+
+    #[test]
+    fn any_and_first_by_predicate() {
+        let db = new_db_with_entries("testdb");
+        let matching = [
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_starts_with("name", "co"),
+        ];
+        assert!(db.any_by_predicate(&matching));
+        assert_eq!(db.first_by_predicate(&matching), Some(RowId(2)));
+
+        let not_matching = [
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_starts_with("name", "x"),
+        ];
+        assert!(!db.any_by_predicate(&not_matching));
+        assert_eq!(db.first_by_predicate(&not_matching), None);
+
+        let unknown = [Predicate::new_equal_string("set", "en-es")];
+        assert!(!db.any_by_predicate(&unknown));
+        assert!(db.any_by_predicate(&[]));
+        assert!(!Db::new("testdb").any_by_predicate(&[]));
+    }
+
+    #[test]
+    fn export_jsonl() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row(vec![Entry::new_i32("add_counter", 3)]);
+        let mut out = vec![];
+        db.export_jsonl(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 3);
+
+        let mut rows = HashMap::new();
+        for line in out.lines() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            let row_id = RowId(value["row_id"].as_u64().unwrap() as usize);
+            let entries: Vec<Entry> = serde_json::from_value(value["entries"].clone()).unwrap();
+            rows.insert(row_id, entries);
+        }
+        assert_eq!(rows, db.by_row_id);
+    }
+
+    #[test]
+    fn import_jsonl() {
+        let db = new_db_with_entries("testdb");
+        let mut out = vec![];
+        db.export_jsonl(&mut out).unwrap();
+        out.extend_from_slice(b"\n   \n");
+
+        let mut imported = Db::new("testdb");
+        let row_ids = imported.import_jsonl(out.as_slice()).unwrap();
+        assert_eq!(row_ids, vec![RowId(1), RowId(2)]);
+        assert_eq!(imported.by_row_id, db.by_row_id);
+
+        let bad = "{\"row_id\":1,\"entries\":[]}\n\n{\"row_id\":2,\"entries\":\n";
+        match imported.import_jsonl(bad.as_bytes()) {
+            Err(DbError::Jsonl { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a JSON Lines error, got {:?}", other),
+        }
+        assert_eq!(imported.row_count(), 2);
+    }
+
+    #[test]
+    fn datetime_display_round_trip() {
+        let data = Db::db_datetime("2018-01-01 10:30:15").unwrap();
+        assert_eq!(data.to_string(), "2018-01-01 10:30:15");
+        assert_eq!(Db::db_datetime(&data.to_string()).unwrap(), data);
+
+        let fmt = "%d.%m.%Y %H:%M:%S";
+        let formatted = data.format_with(fmt).unwrap();
+        assert_eq!(formatted, "01.01.2018 10:30:15");
+        let parsed = NaiveDateTime::parse_from_str(&formatted, fmt).unwrap();
+        assert_eq!(Data::DbDateTime(parsed), data);
+    }
+
+    #[test]
+    fn db_datetime_flexible() {
+        let expected = |t| Db::db_datetime(t).unwrap();
+        assert_eq!(
+            Db::db_datetime_flexible("2013-11-22 12:30:15").unwrap(),
+            expected("2013-11-22 12:30:15")
+        );
+        assert_eq!(
+            Db::db_datetime_flexible("2013-11-22 12:30").unwrap(),
+            expected("2013-11-22 12:30:00")
+        );
+        assert_eq!(
+            Db::db_datetime_flexible(" 2013-11-22 ").unwrap(),
+            expected("2013-11-22 00:00:00")
+        );
+        assert_eq!(
+            Db::db_datetime_flexible("2013-11-22T12:30:15+02:00").unwrap(),
+            expected("2013-11-22 12:30:15")
+        );
+        assert_eq!(
+            Db::db_datetime_flexible("2013-11-22T12:30:15Z").unwrap(),
+            expected("2013-11-22 12:30:15")
+        );
+        match Db::db_datetime_flexible("22/11/2013") {
+            Err(DbError::DateTimeFormats { value, tried }) => {
+                assert_eq!(value, "22/11/2013");
+                assert_eq!(tried.len(), 4);
+            }
+            other => panic!("expected a format error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn row_entries() {
+        let mut db = new_db_with_entries("testdb");
+        db.append_value(RowId(2), "value", Db::db_string("automobile"));
+        assert_eq!(
+            db.row_entries(RowId(2)).unwrap(),
+            [
+                Entry::new_string("set", "es-en"),
+                Entry::new_string("name", "coche"),
+                Entry::new_string("value", "car"),
+                Entry::new_string("value", "automobile"),
+            ]
+        );
+        assert_eq!(db.row_entries(RowId(99)), None);
+    }
+
+    #[test]
+    fn rename_entry() {
+        let mut db = new_db_with_entries("testdb");
+        let row_3 = db.add_row(vec![
+            Entry::new_string("name", "moto"),
+            Entry::new_string("translation", "motorbike"),
+            Entry::new_string("value", "motorcycle"),
+        ]);
+        assert_eq!(db.rename_entry("value", "translation"), 3);
+        assert_eq!(db.rename_entry("value", "translation"), 0);
+
+        assert!(db.find_row_ids_by_name("value").is_empty());
+        assert!(db
+            .find_row_ids_by_value("value", &Db::db_string("car"))
+            .is_empty());
+        assert_eq!(
+            db.find_row_ids_by_value("translation", &Db::db_string("car")),
+            vec![RowId(2)]
+        );
+        let mut row_ids = db.find_row_ids_by_name("translation");
+        row_ids.sort();
+        assert_eq!(row_ids, vec![RowId(1), RowId(2), row_3]);
+        assert_eq!(
+            db.entries_from_row_ids(&[row_3], &["translation"]),
+            vec![vec![
+                Entry::new_string("translation", "motorbike"),
+                Entry::new_string("translation", "motorcycle"),
+            ]]
+        );
+    }
+
+    #[test]
+    fn replace_value() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row(vec![
+            Entry::new_string("name", "auto"),
+            Entry::new_string("value", "car"),
+            Entry::new_string("value", "car"),
+        ]);
+        db.add_row(vec![Entry::new_string("other", "car")]);
+        let car = Db::db_string("car");
+        let automobile = Db::db_string("automobile");
+
+        assert_eq!(db.replace_value("value", &car, automobile.clone()), 3);
+        assert!(db.find_row_ids_by_value("value", &car).is_empty());
+        assert_eq!(db.find_row_ids_by_value("other", &car), vec![RowId(4)]);
+        let mut row_ids = db.find_row_ids_by_value("value", &automobile);
+        row_ids.sort();
+        assert_eq!(row_ids, vec![RowId(2), RowId(3)]);
+        assert_eq!(db.replace_value("value", &car, automobile), 0);
+    }
+
+    #[test]
+    fn dedup_rows() {
+        let mut db = Db::new("testdb");
+        let row_1 = db.add_row(vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_string("value", "car"),
+        ]);
+        let row_2 = db.add_row(vec![
+            Entry::new_string("name", "moto"),
+            Entry::new_string("value", "motorbike"),
+        ]);
+        let _row_3 = db.add_row(vec![
+            Entry::new_string("value", "car"),
+            Entry::new_string("name", "coche"),
+        ]);
+        assert_eq!(db.dedup_rows(), 1);
+        let mut row_ids = db.find_all_row_ids();
+        row_ids.sort();
+        assert_eq!(row_ids, vec![row_1, row_2]);
+        assert_eq!(db.dedup_rows(), 0);
+    }
+
+    #[test]
+    fn compact() {
+        let mut db = Db::new("testdb");
+        let row_ids = (0..5)
+            .map(|i| db.add_row(vec![Entry::new_i32("number", i)]))
+            .collect::<Vec<RowId>>();
+        db.delete_rows(&[row_ids[1], row_ids[3]]);
+
+        let mapping = db.compact();
+        let mut expected = HashMap::new();
+        expected.insert(row_ids[0], RowId(1));
+        expected.insert(row_ids[2], RowId(2));
+        expected.insert(row_ids[4], RowId(3));
+        assert_eq!(mapping, expected);
+
+        let mut all = db.find_all_row_ids();
+        all.sort();
+        assert_eq!(all, vec![RowId(1), RowId(2), RowId(3)]);
+        assert_eq!(db.get_i32(RowId(3), "number"), Some(4));
+        assert_eq!(
+            db.find_row_ids_by_value("number", &Db::db_i32(2)),
+            vec![RowId(2)]
+        );
+        assert!(db
+            .find_row_ids_by_value("number", &Db::db_i32(1))
+            .is_empty());
+        assert_eq!(db.add_i32("number", 5), RowId(4));
+
+        db.delete_rows(&[RowId(1)]);
+        let mapping = db.compact_keep_row_max();
+        assert_eq!(mapping[&RowId(4)], RowId(3));
+        assert_eq!(db.row_max(), 4);
+        assert_eq!(db.add_i32("number", 6), RowId(5));
+    }
+
+    #[test]
+    fn save_with_backup() {
+        let name = "testdb-backup";
+        let _ = std::fs::remove_file(format!("save/{}.bak", name));
+        let mut db = Db::new(name);
+        db.add_string("word", "coche");
+        db.save_with_backup().unwrap();
+        let first = std::fs::read_to_string(format!("save/{}", name)).unwrap();
+
+        db.add_string("word", "moto");
+        db.save_with_backup().unwrap();
+        let backup = std::fs::read_to_string(format!("save/{}.bak", name)).unwrap();
+        assert_eq!(backup, first);
+        assert_eq!(Db::load(name).unwrap().row_count(), 2);
+
+        db.add_string("word", "bici");
+        db.save_with_backup().unwrap();
+        let backup = std::fs::read_to_string(format!("save/{}.bak", name)).unwrap();
+        assert!(backup.contains("moto"));
+        assert!(!backup.contains("bici"));
+    }
+
+    #[test]
+    fn on_change() {
+        let mut db = new_db_with_entries("testdb");
+        let events = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&events);
+        db.on_change(Box::new(move |event| log.lock().unwrap().push(event)));
+
+        let row_3 = db.add_row(vec![Entry::new_i32("add_counter", 1)]);
+        db.add_or_update_entry(row_3, Entry::new_i32("add_counter", 2));
+        db.update_value(row_3, "add_counter", Db::db_i32(3));
+        db.update_value(row_3, "missing", Db::db_i32(3));
+        db.delete_rows(&[RowId(1), RowId(99)]);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ChangeEvent::RowAdded(row_3),
+                ChangeEvent::EntryUpdated(row_3, String::from("add_counter")),
+                ChangeEvent::EntryUpdated(row_3, String::from("add_counter")),
+                ChangeEvent::RowDeleted(RowId(1)),
+            ]
+        );
+
+        // Clones do not share the callback
+        let mut copy = db.clone();
+        copy.add_i32("add_counter", 1);
+        assert_eq!(events.lock().unwrap().len(), 4);
+        assert_eq!(copy.row_count(), db.row_count() + 1);
+    }
+
+    #[test]
+    fn find_prefix_multibyte() {
+        let mut db = Db::new("testdb");
+        let row_1 = db.add_string("word", "coció");
+        let row_2 = db.add_string("word", "cocinó");
+        let _coche = db.add_string("word", "coche");
+        let _other = db.add_string("other", "coció");
+
+        for indexed in &[false, true] {
+            if *indexed {
+                db.build_prefix_index();
+            }
+            let mut prefix = String::from("coció");
+            prefix.pop();
+            assert_eq!(prefix, "coci");
+            assert_eq!(db.find_prefix("word", &prefix), vec![row_1, row_2]);
+            assert_eq!(db.find_prefix("word", "coció"), vec![row_1]);
+            assert_eq!(db.find_prefix("word", "cocio"), vec![]);
+            assert_eq!(db.find_prefix("word", "ó"), vec![]);
+        }
+    }
+
+    #[test]
+    fn find_value_in_any_name() {
+        let mut db = Db::new("testdb");
+        let fue_name = db.add_row(vec![Entry::new_string("name", "fue")]);
+        let ser = db.add_row(vec![
+            Entry::new_string("name", "ser"),
+            Entry::new_string("conjugation", "fue"),
+        ]);
+        let ir = db.add_row(vec![
+            Entry::new_string("name", "ir"),
+            Entry::new_string("conjugation", "fue"),
+        ]);
+        let _other = db.add_row(vec![Entry::new_string("translation", "fue")]);
+
+        let fue = Db::db_string("fue");
+        assert_eq!(
+            db.find_value_in_any_name(&["name", "conjugation"], &fue),
+            vec![fue_name, ser, ir]
+        );
+        assert_eq!(db.find_value_in_any_name(&["name"], &fue), vec![fue_name]);
+        assert!(db.find_value_in_any_name(&[], &fue).is_empty());
+    }
+
+    #[test]
+    fn data_ordering() {
+        let early = Db::db_datetime("2013-11-22 12:00:00").unwrap();
+        let late = Db::db_datetime("2018-01-01 00:00:00").unwrap();
+        let mut data = vec![
+            late.clone(),
+            Db::db_string("b"),
+            Db::db_i32(10),
+            early.clone(),
+            Db::db_string("B"),
+            Db::db_i32(-3),
+            Db::db_string("a"),
+        ];
+        data.sort();
+        assert_eq!(
+            data,
+            vec![
+                Db::db_i32(-3),
+                Db::db_i32(10),
+                Db::db_string("B"),
+                Db::db_string("a"),
+                Db::db_string("b"),
+                early.clone(),
+                late.clone(),
+            ]
+        );
+
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(late, "late");
+        map.insert(Db::db_i32(1), "one");
+        map.insert(early, "early");
+        assert_eq!(
+            map.values().cloned().collect::<Vec<_>>(),
+            ["one", "early", "late"]
+        );
+    }
+
+    #[test]
+    fn last_n_rows_with_gaps() {
+        let mut db = Db::new("testdb");
+        let row_ids = (0..10_000)
+            .map(|i| db.add_row(vec![Entry::new_i32("number", i)]))
+            .collect::<Vec<RowId>>();
+        let deleted = row_ids
+            .iter()
+            .filter(|row_id| row_id.0 % 7 != 0)
+            .cloned()
+            .collect::<Vec<RowId>>();
+        db.delete_rows(&deleted);
+
+        let mut expected = row_ids
+            .into_iter()
+            .filter(|row_id| row_id.0 % 7 == 0)
+            .collect::<Vec<RowId>>();
+        expected.reverse();
+        assert_eq!(db.last_n_rows(5), expected[..5]);
+        assert_eq!(db.last_n_rows(expected.len()), expected);
+        assert_eq!(db.last_n_rows(expected.len() + 1), expected);
+        assert_eq!(db.last_n_rows(1), vec![RowId(9996)]);
+    }
+
+    #[test]
+    fn find_row_ids_by_predicate_unsorted() {
+        let mut db = Db::new("testdb");
+        for i in 0..50 {
+            db.add_row(vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_i32("number", i),
+            ]);
+        }
+        let predicates = [Predicate::new_equal_string("set", "es-en")];
+        let sorted = db.find_row_ids_by_predicate(&predicates, None);
+        let unsorted = db.find_row_ids_by_predicate_unsorted(&predicates, None);
+        assert_eq!(unsorted, db.find_by_predicate(&predicates[0]));
+        assert_ne!(unsorted, sorted);
+        let mut resorted = unsorted.clone();
+        resorted.sort();
+        assert_eq!(resorted, sorted);
+
+        let limited = db.find_row_ids_by_predicate_unsorted(&predicates, Some(3));
+        assert_eq!(limited, unsorted[..3]);
+    }
+
+    #[test]
+    fn from_rows() {
+        let db = Db::from_rows(
+            "testdb",
+            vec![
+                vec![
+                    Entry::new_string("set", "es-en"),
+                    Entry::new_string("name", "disfrutar"),
+                    Entry::new_string("value", "to enjoy"),
+                ],
+                vec![
+                    Entry::new_string("set", "es-en"),
+                    Entry::new_string("name", "coche"),
+                    Entry::new_string("value", "car"),
+                ],
+            ],
+        );
+        assert_eq!(db, new_db_with_entries("testdb"));
+        check_single_entries(&db);
+    }
+
+    #[test]
+    fn load_or_new() {
+        let name = "testdb-load-or-new";
+        let path = format!("save/{}", name);
+
+        let _ = std::fs::remove_file(&path);
+        let db = Db::load_or_new(name).unwrap();
+        assert_eq!(db, Db::new(name));
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(Db::load_or_new(name).unwrap(), Db::new(name));
+        std::fs::write(&path, "\n").unwrap();
+        assert_eq!(Db::load_or_new(name).unwrap(), Db::new(name));
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(Db::load_or_new(name), Err(DbError::Serde(_))));
+
+        let mut db = new_db_with_entries(name);
+        db.save().unwrap();
+        assert_eq!(Db::load_or_new(name).unwrap(), db);
+    }
+
+    #[test]
+    fn add_row_checked() {
+        let mut db = Db::new("testdb");
+        let two_names = vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_string("name", "auto"),
+            Entry::new_string("value", "car"),
+        ];
+        match db.add_row_checked(two_names, &["name", "value"]) {
+            Err(DbError::DuplicateName(name)) => assert_eq!(name, "name"),
+            other => panic!("expected a duplicate name error, got {:?}", other),
+        }
+        assert_eq!(db.row_count(), 0);
+
+        let two_values = vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_string("value", "car"),
+            Entry::new_string("value", "automobile"),
+        ];
+        let row_id = db.add_row_checked(two_values, &["name"]).unwrap();
+        assert_eq!(db.row_entries(row_id).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn find_rows_by_name_and_kind() {
+        let mut db = Db::new("testdb");
+        let dt = NaiveDateTime::parse_from_str("2013-11-22 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let text = db.add_row(vec![Entry::new_string("x", "5")]);
+        let number = db.add_row(vec![Entry::new_i32("x", 5)]);
+        let both = db.add_row(vec![Entry::new_i32("x", 6), Entry::new_string("x", "6")]);
+        let date = db.add_row(vec![Entry::new_datetime("x", dt)]);
+        let _other = db.add_row(vec![Entry::new_i32("y", 5)]);
+
+        assert_eq!(
+            db.find_rows_by_name_and_kind("x", DataKind::String),
+            vec![text, both]
+        );
+        assert_eq!(
+            db.find_rows_by_name_and_kind("x", DataKind::I32),
+            vec![number, both]
+        );
+        assert_eq!(
+            db.find_rows_by_name_and_kind("x", DataKind::DateTime),
+            vec![date]
+        );
+        assert!(db.find_rows_by_name_and_kind("z", DataKind::I32).is_empty());
+    }
+
+    #[test]
+    fn entries_from_row_ids_with_strings() {
+        let db = new_db_with_entries("testdb");
+        let row_ids = [RowId(1), RowId(2)];
+        let names = vec![String::from("name"), String::from("value")];
+        let expected = db.entries_from_row_ids(&row_ids, &["name", "value"]);
+        assert_eq!(db.entries_from_row_ids(&row_ids, &names), expected);
+        assert_eq!(
+            db.entries_from_row_ids(&row_ids, names.as_slice()),
+            expected
+        );
+        assert_eq!(
+            db.entries_map_from_row_ids(&row_ids, &names)[&RowId(2)],
+            expected[1]
+        );
+        let predicates = [Predicate::new_equal_string("set", "es-en")];
+        assert_eq!(db.select(&predicates, &names, None), expected);
+    }
+
+    #[test]
+    fn find_rows_missing() {
+        let mut db = new_db_with_entries("testdb");
+        let undated = db.add_row(vec![Entry::new_string("name", "moto")]);
+        let dated = db.add_row(vec![
+            Entry::new_string("name", "bici"),
+            Entry::new_datetime_str("add_date", "2013-11-22 12:00:00").unwrap(),
+        ]);
+        assert_eq!(
+            db.find_rows_missing("add_date"),
+            vec![RowId(1), RowId(2), undated]
+        );
+        assert_eq!(db.find_rows_missing("set"), vec![undated, dated]);
+        assert!(db.find_rows_missing("name").is_empty());
+        assert_eq!(db.find_rows_missing("unknown").len(), 4);
+    }
+
+    #[test]
+    fn save_subset() {
+        let mut db = new_db_with_entries("testdb");
+        let moto = db.add_row(vec![Entry::new_string("name", "moto")]);
+        let before = db.clone();
+        db.save_subset(&[moto, RowId(99), RowId(2)], "testdb-subset")
+            .unwrap();
+        assert_eq!(db, before);
+
+        let subset = Db::load("testdb-subset").unwrap();
+        let mut row_ids = subset.find_all_row_ids();
+        row_ids.sort();
+        assert_eq!(row_ids, vec![RowId(1), RowId(2)]);
+        assert_eq!(subset.row_entries(RowId(1)), db.row_entries(moto));
+        assert_eq!(subset.row_entries(RowId(2)), db.row_entries(RowId(2)));
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn equal_folded() {
+        let mut db = Db::new("testdb");
+        let cocer = db.add_string("word", "cocer");
+        let coc_er = db.add_string("word", "cocér");
+        let upper = db.add_string("word", "COCER");
+        let nino = db.add_string("word", "niño");
+        let _other = db.add_string("word", "coser");
+        let _number = db.add_i32("word", 1);
+
+        let predicates = [Predicate::new_equal_folded("word", "cocer")];
+        assert_eq!(
+            db.find_row_ids_by_predicate(&predicates, None),
+            vec![cocer, coc_er, upper]
+        );
+        let predicates = [Predicate::new_equal_folded("word", "Cocér")];
+        assert_eq!(db.count_by_predicate(&predicates), 3);
+        let predicates = [Predicate::new_equal_folded("word", "nino")];
+        assert_eq!(db.find_row_ids_by_predicate(&predicates, None), vec![nino]);
+        // Decomposed input: "n" followed by a combining tilde
+        let predicates = [Predicate::new_equal_folded("word", "nin\u{303}o")];
+        assert_eq!(db.find_row_ids_by_predicate(&predicates, None), vec![nino]);
+    }
+
+    #[test]
+    fn move_entry() {
+        let mut db = new_db_with_entries("testdb");
+        let cocina = db.add_row(vec![
+            Entry::new_string("name", "cocina"),
+            Entry::new_string("value", "kitchen"),
+            Entry::new_string("value", "car"),
+        ]);
+        let car = Db::db_string("car");
+
+        assert!(db.move_entry(cocina, RowId(2), "value", &car));
+        assert_eq!(
+            db.row_entries(cocina).unwrap(),
+            [
+                Entry::new_string("name", "cocina"),
+                Entry::new_string("value", "kitchen"),
+            ]
+        );
+        assert_eq!(
+            db.entries_from_row_ids(&[RowId(2)], &["value"]),
+            vec![vec![
+                Entry::new_string("value", "car"),
+                Entry::new_string("value", "car"),
+            ]]
+        );
+        assert_eq!(db.find_row_ids_by_value("value", &car), vec![RowId(2)]);
+        assert!(!db.move_entry(cocina, RowId(2), "value", &car));
+        assert!(!db.move_entry(cocina, RowId(99), "value", &Db::db_string("kitchen")));
+
+        // Moving the last entry deletes the row
+        let single = db.add_row(vec![Entry::new_string("value", "stove")]);
+        assert!(db.move_entry(single, cocina, "value", &Db::db_string("stove")));
+        assert!(!db.find_all_row_ids().contains(&single));
+        assert!(db.find_row_ids_by_name("value").contains(&cocina));
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("stove")),
+            vec![cocina]
+        );
+    }
+
+    #[test]
+    fn contains_row() {
+        let mut db = new_db_with_entries("testdb");
+        assert!(db.contains_row(RowId(1)));
+        assert!(db.contains_row(RowId(2)));
+        assert!(!db.contains_row(RowId(3)));
+        db.delete_rows(&[RowId(1)]);
+        assert!(!db.contains_row(RowId(1)));
+        assert!(db.contains_row(RowId(2)));
+    }
+
+    #[test]
+    fn row_max_high_water_mark() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.row_max(), 2);
+        db.delete_rows(&[RowId(2)]);
+        assert_eq!(db.row_max(), 2);
+        assert_eq!(db.add_string("name", "moto"), RowId(3));
+
+        db.set_row_max(10);
+        assert_eq!(db.add_string("name", "bici"), RowId(11));
+        db.set_row_max(0);
+        assert_eq!(db.row_max(), 11);
+        db.delete_rows(&[RowId(11)]);
+        db.set_row_max(0);
+        assert_eq!(db.row_max(), 3);
+        assert_eq!(db.add_string("name", "barco"), RowId(4));
+    }
+
+    #[test]
+    fn set_row_max_marks_dirty() {
+        let mut db = new_db_with_entries("testdb-row-max-dirty");
+        db.save().unwrap();
+        db.set_row_max(2);
+        assert!(!db.is_dirty());
+        db.set_row_max(10);
+        assert!(db.save_if_dirty().unwrap());
+        assert_eq!(Db::load("testdb-row-max-dirty").unwrap().row_max(), 10);
+    }
+
+    #[test]
+    fn row_max_is_saved() {
+        let mut db = new_db_with_entries("testdb-row-max-saved");
+        db.delete_rows(&[RowId(2)]);
+        db.save().unwrap();
+        let mut loaded = Db::load("testdb-row-max-saved").unwrap();
+        assert_eq!(loaded.row_max(), 2);
+        assert_eq!(loaded.add_string("name", "moto"), RowId(3));
+
+        std::fs::write(
+            "save/testdb-row-max-legacy",
+            r#"{"1": [{"name": "name", "value": {"DbString": "coche"}}]}"#,
+        )
+        .unwrap();
+        let mut legacy = Db::load("testdb-row-max-legacy").unwrap();
+        assert_eq!(
+            legacy.get_string(RowId(1), "name"),
+            Some(String::from("coche"))
+        );
+        assert_eq!(legacy.add_string("name", "moto"), RowId(2));
+    }
+
+    #[test]
+    fn equal_predicate_with_max_results() {
+        let mut db = Db::new("testdb");
+        for i in 0..100 {
+            db.add_row(vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_i32("number", i),
+            ]);
+        }
+        let predicates = [Predicate::new_equal_string("set", "es-en")];
+        let first = db.find_row_ids_by_predicate(&predicates, Some(1));
+        assert_eq!(first, [RowId(1)]);
+        assert!(db
+            .find_row_ids_by_predicate(&[Predicate::new_equal_string("set", "de-en")], Some(1))
+            .is_empty());
+        let unbounded = db.find_row_ids_by_predicate_unsorted(&predicates, None);
+        assert_eq!(
+            db.find_row_ids_by_predicate_unsorted(&predicates, Some(1)),
+            [unbounded[0]]
+        );
+        assert_eq!(db.find_row_ids_by_predicate(&predicates, Some(5)).len(), 5);
+        assert_eq!(
+            db.find_row_ids_by_predicate(&predicates, Some(0)),
+            Vec::<RowId>::new()
+        );
+
+        let predicates = [
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_equal_i32("number", 42),
+        ];
+        assert_eq!(
+            db.find_row_ids_by_predicate(&predicates, Some(1)),
+            vec![RowId(43)]
+        );
+    }
+
+    #[test]
+    fn query_result() {
+        let mut db = new_db_with_entries("testdb");
+        db.append_value(RowId(2), "value", Db::db_string("automobile"));
+        let predicates = [Predicate::new_equal_string("set", "es-en")];
+        let result = QueryResult::from(db.select(&predicates, &["name", "value"], None));
+
+        assert_eq!(result.len(), 2);
+        assert!(!result.is_empty());
+        assert_eq!(result.get(0, "name"), Some(&Db::db_string("disfrutar")));
+        assert_eq!(result.get(1, "value"), Some(&Db::db_string("car")));
+        assert_eq!(
+            result.get_all(1, "value"),
+            vec![&Db::db_string("car"), &Db::db_string("automobile")]
+        );
+        assert_eq!(result.get(0, "set"), None);
+        assert_eq!(result.get(2, "name"), None);
+        assert!(result.get_all(2, "name").is_empty());
+        assert_eq!(result.rows()[0].len(), 2);
+        assert!(QueryResult::default().is_empty());
+    }
+
+    #[test]
+    fn retain_by_predicate() {
+        let mut db = new_db_with_entries("testdb");
+        let de_en = db.add_row(vec![
+            Entry::new_string("set", "de-en"),
+            Entry::new_string("name", "Auto"),
+            Entry::new_string("value", "car"),
+        ]);
+        let _no_set = db.add_row(vec![Entry::new_string("name", "moto")]);
+
+        assert_eq!(db.retain_by_predicate(&[]), 0);
+        assert_eq!(
+            db.retain_by_predicate(&[Predicate::new_equal_string("set", "es-en")]),
+            2
+        );
+        let mut row_ids = db.find_all_row_ids();
+        row_ids.sort();
+        assert_eq!(row_ids, vec![RowId(1), RowId(2)]);
+        assert!(db
+            .find_row_ids_by_value("set", &Db::db_string("de-en"))
+            .is_empty());
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("car")),
+            vec![RowId(2)]
+        );
+        assert!(!db.contains_row(de_en));
+        check_single_entries(&db);
+    }
+
+    #[test]
+    fn save_if_dirty() {
+        let name = "testdb-dirty";
+        let path = format!("save/{}", name);
+        let mut db = new_db_with_entries(name);
+        assert!(db.is_dirty());
+        db.save().unwrap();
+        assert!(!db.is_dirty());
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!db.save_if_dirty().unwrap());
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            modified
+        );
+
+        let mut loaded = Db::load(name).unwrap();
+        assert!(!loaded.is_dirty());
+        loaded.update_value(RowId(2), "value", Db::db_string("automobile"));
+        assert!(loaded.is_dirty());
+        assert!(loaded.save_if_dirty().unwrap());
+        assert!(!loaded.save_if_dirty().unwrap());
+        assert_ne!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            modified
+        );
+
+        loaded.delete_rows(&[RowId(1)]);
+        assert!(loaded.is_dirty());
+        assert_eq!(Db::load(name).unwrap().row_count(), 2);
+    }
+
+    #[test]
+    fn i64_beyond_i32() {
+        let big = i64::from(i32::MAX) + 1_000;
+        let mut db = Db::new("testdb");
+        let row_1 = db.add_row(vec![Entry::new_i64("bytes", big)]);
+        let row_2 = db.add_row(vec![Entry::new_i32("bytes", 5)]);
+        assert_eq!(
+            db.find_row_ids_by_value("bytes", &Db::db_i64(big)),
+            vec![row_1]
+        );
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[Predicate::new_equal_i64("bytes", big)], None),
+            vec![row_1]
+        );
+        assert_eq!(db.get_i64(row_1, "bytes"), Some(big));
+        assert_eq!(db.get_i64(row_2, "bytes"), Some(5));
+        assert_eq!(db.get_i32(row_1, "bytes"), None);
+        assert_eq!(Db::db_i64(big).kind(), DataKind::I64);
+        assert_eq!(db.sum_i64("bytes"), i128::from(big) + 5);
+        assert_eq!(db.min_i64("bytes"), Some(5));
+        assert_eq!(db.max_i64("bytes"), Some(big));
+        assert_eq!(db.avg_i64("bytes"), Some((big as f64 + 5.0) / 2.0));
+    }
+
+    #[test]
+    fn diff() {
+        let db = new_db_with_entries("testdb");
+        assert!(db.diff(&db.clone()).is_empty());
+
+        let mut other = db.clone();
+        other.delete_rows(&[RowId(1)]);
+        other.add_or_update_entry(RowId(2), Entry::new_string("value", "automobile"));
+        let added = other.add_string("name", "moto");
+        assert_eq!(
+            db.diff(&other),
+            DbDiff {
+                only_in_self: vec![RowId(1)],
+                only_in_other: vec![added],
+                changed: vec![RowId(2)],
+            }
+        );
+        assert_eq!(other.diff(&db).only_in_self, vec![added]);
+    }
+
+    #[test]
+    fn enable_timestamps() {
+        let mut db = Db::new("testdb-timestamps");
+        let unstamped = db.add_string("name", "coche");
+        db.enable_timestamps();
+        let row_id = db.add_string("name", "moto");
+        let rows = db.add_rows(vec![vec![Entry::new_string("name", "barco")]]);
+
+        assert_eq!(db.get_datetime(unstamped, CREATED_AT), None);
+        assert!(db.get_datetime(row_id, CREATED_AT).is_some());
+        assert!(db.get_datetime(rows[0], CREATED_AT).is_some());
+        assert_eq!(db.find_row_ids_by_name(CREATED_AT).len(), 2);
+
+        db.save().unwrap();
+        let db = Db::load("testdb-timestamps").unwrap();
+        assert_eq!(
+            db.first_value(row_id, CREATED_AT).map(|value| value.kind()),
+            Some(DataKind::DateTime)
+        );
+    }
+
+    #[test]
+    fn load_readonly() {
+        let mut db = new_db_with_entries("testdb-readonly");
+        db.save().unwrap();
+        let db = Db::load_readonly("testdb-readonly").unwrap();
+        check_single_entries(&db);
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[Predicate::new_equal_string("name", "coche")], None),
+            vec![RowId(2)]
+        );
+    }
+
+    #[test]
+    fn run_query() {
+        let mut db = new_db_with_entries("testdb");
+        let _row_id = db.add_row(vec![
+            Entry::new_string("set", "de-en"),
+            Entry::new_string("name", "Auto"),
+            Entry::new_string("value", "car"),
+        ]);
+        let _row_id = db.add_row(vec![
+            Entry::new_string("set", "es-en"),
+            Entry::new_string("name", "moto"),
+        ]);
+
+        // (set = es-en and value = car) or (name starts with A and value = car)
+        let es = Query::new()
+            .and(Predicate::new_equal_string("set", "es-en"))
+            .and(Predicate::new_any("value"));
+        let query = Query::new()
+            .and(Predicate::new_starts_with("name", "A"))
+            .or_query(es)
+            .and_query(Query::new().and(Predicate::new_equal_string("value", "car")));
+
+        let mut expected = db.find_row_ids_by_predicate(
+            &[
+                Predicate::new_equal_string("set", "es-en"),
+                Predicate::new_any("value"),
+                Predicate::new_equal_string("value", "car"),
+            ],
+            None,
+        );
+        expected.extend(db.find_row_ids_by_predicate(
+            &[
+                Predicate::new_starts_with("name", "A"),
+                Predicate::new_equal_string("value", "car"),
+            ],
+            None,
+        ));
+        expected.sort();
+        assert_eq!(expected, [RowId(2), RowId(3)]);
+        assert_eq!(db.run(&query), expected);
+        assert_eq!(db.run(&query.limit(1)), [RowId(2)]);
+        assert_eq!(db.run(&Query::new()).len(), 4);
+    }
+
+    #[test]
+    fn name_cardinality_and_value_frequency() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.name_cardinality("set"), 1);
+        assert_eq!(db.name_cardinality("name"), 2);
+        assert_eq!(db.name_cardinality("missing"), 0);
+        assert_eq!(db.value_frequency("set", &Db::db_string("es-en")), 2);
+        assert_eq!(db.value_frequency("name", &Db::db_string("coche")), 1);
+        assert_eq!(db.value_frequency("name", &Db::db_string("moto")), 0);
+
+        db.delete_rows(&[RowId(2)]);
+        assert_eq!(db.name_cardinality("name"), 1);
+        assert_eq!(db.value_frequency("set", &Db::db_string("es-en")), 1);
+    }
+
+    #[test]
+    fn find_row_ids_optimized() {
+        let mut db = new_db_with_entries("testdb");
+        for i in 0..50 {
+            db.add_row(vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_string("name", &format!("word{}", i)),
+            ]);
+        }
+        // The broad "set" predicate first is the slow order
+        let bad_order = vec![
+            Predicate::new_starts_with("value", "car"),
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_equal_string("name", "coche"),
+        ];
+        let expected = db.find_row_ids_by_predicate(&bad_order, None);
+        assert_eq!(expected, [RowId(2)]);
+        assert_eq!(db.find_row_ids_optimized(&bad_order, None), expected);
+        assert_eq!(db.find_row_ids_optimized(&bad_order, Some(1)), expected);
+
+        let no_equality = vec![Predicate::new_starts_with("name", "word1")];
+        assert_eq!(
+            db.find_row_ids_optimized(&no_equality, None),
+            db.find_row_ids_by_predicate(&no_equality, None)
+        );
+        let no_match = vec![
+            Predicate::new_equal_string("set", "es-en"),
+            Predicate::new_equal_string("name", "moto"),
+        ];
+        assert!(db.find_row_ids_optimized(&no_match, None).is_empty());
+    }
+
+    #[test]
+    fn remove_entry() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_or_update_entry(RowId(2), Entry::new_string("set", "es-en"));
+        db.add_row_id_entry(RowId(2), Entry::new_string("value", "automobile"));
+        db.add_row_id_entry(RowId(2), Entry::new_string("value", "auto"));
+
+        assert!(db.remove_entry(RowId(2), "value", &Db::db_string("automobile")));
+        assert!(!db.remove_entry(RowId(2), "value", &Db::db_string("automobile")));
+        assert!(!db.remove_entry(RowId(99), "value", &Db::db_string("car")));
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("automobile")),
+            vec![]
+        );
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("car")),
+            vec![RowId(2)]
+        );
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("auto")),
+            vec![RowId(2)]
+        );
+        let values = db
+            .row_entries(RowId(2))
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.name == "value")
+            .cloned()
+            .collect::<Vec<Entry>>();
+        assert_eq!(
+            values,
+            vec![
+                Entry::new_string("value", "car"),
+                Entry::new_string("value", "auto")
+            ]
+        );
+    }
+
+    #[test]
+    fn get_first_by_name_ref() {
+        let db = new_db_with_entries("testdb");
+        let entries = db.row_entries(RowId(1)).unwrap();
+        let entry = Entry::get_first_by_name_ref(entries, "name").unwrap();
+        assert!(std::ptr::eq(entry, &entries[1]));
+        assert_eq!(
+            Some(entry.clone()),
+            Entry::get_first_by_name(entries, "name")
+        );
+        assert_eq!(Entry::get_first_by_name_ref(entries, "missing"), None);
+    }
+
+    #[test]
+    fn find_row_ids_by_predicate_limit_after_sort() {
+        let mut db = Db::new("testdb");
+        for i in 0..100 {
+            db.add_row(vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_string("value", &format!("car{}", i)),
+                Entry::new_string("value", "car"),
+            ]);
+        }
+        let all = db.find_row_ids_by_predicate(&[Predicate::new_starts_with("value", "car")], None);
+        assert_eq!(all.len(), 100);
+        for predicates in &[
+            vec![Predicate::new_starts_with("value", "car")],
+            vec![Predicate::new_equal_string("value", "car")],
+            vec![
+                Predicate::new_equal_string("set", "es-en"),
+                Predicate::new_starts_with("value", "car"),
+            ],
+        ] {
+            let row_ids = db.find_row_ids_by_predicate(predicates, Some(3));
+            assert_eq!(row_ids, [RowId(1), RowId(2), RowId(3)]);
+        }
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[], Some(2)),
+            [RowId(1), RowId(2)]
+        );
     }
 
-    #[cfg(test)]
-    fn check_single_entries(db: &Db) {
-        assert_eq!(db.by_row_id.len(), 2);
+    #[test]
+    fn replay_wal() {
+        let wal_path = "save/testdb-wal.wal";
+        let _ = std::fs::remove_file(wal_path);
+        let mut db = new_db_with_entries("testdb-wal");
+        db.save().unwrap();
+        db.enable_wal("testdb-wal.wal").unwrap();
 
-        for value in &db.by_value {
-            println!("{:?}", value);
+        let moto = db.add_row(vec![
+            Entry::new_string("set", "es-en"),
+            Entry::new_string("name", "moto"),
+        ]);
+        db.add_row_id_entry(moto, Entry::new_string("value", "motorbike"));
+        db.update_value(RowId(2), "value", Db::db_string("automobile"));
+        db.add_or_update_entry(RowId(2), Entry::new_i64("add_counter", 1));
+        db.delete_rows(&[RowId(1)]);
+        db.rename_entry("set", "language");
+        db.set_row_max(10);
+        {
+            let mut transaction = db.begin();
+            transaction.add_row(vec![Entry::new_string("name", "barco")]);
         }
-        let row_ids = db.find_row_ids_by_value("set", &Db::db_string("es-en"));
-        assert_eq!(row_ids.len(), 2);
 
-        let row_ids = db.find_row_ids_by_value("value", &Db::db_string("car"));
-        println!("find_row_ids_by_value(): {:?}", row_ids);
-        assert_eq!(row_ids.len(), 1);
+        let restored = Db::replay_wal("testdb-wal", "testdb-wal.wal").unwrap();
+        assert!(restored.diff(&db).is_empty());
+        assert_eq!(restored.row_max(), 10);
+        assert_eq!(
+            restored.count_by_predicate(&[Predicate::new_any("language")]),
+            db.count_by_predicate(&[Predicate::new_any("language")])
+        );
+        assert!(restored.is_dirty());
+
+        // Saving empties the log, a replay after that only loads the file
+        db.save().unwrap();
+        assert_eq!(std::fs::read_to_string(wal_path).unwrap(), "");
+        db.add_string("name", "tren");
+        let restored = Db::replay_wal("testdb-wal", "testdb-wal.wal").unwrap();
+        assert!(restored.diff(&db).is_empty());
+
+        // A line cut short by a crash is ignored
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(wal_path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"{\"Row\":{\"row_id\":").unwrap();
+        let restored = Db::replay_wal("testdb-wal", "testdb-wal.wal").unwrap();
+        assert!(restored.diff(&db).is_empty());
     }
 
     #[test]
-    fn find_row_ids_by_predicate() {
-        let name = "testdb";
-        let db = new_db_with_entries(name);
+    fn enable_wal_logs_unsaved_changes() {
+        let _ = std::fs::remove_file("save/testdb-wal-dirty.wal");
+        let mut db = new_db_with_entries("testdb-wal-dirty");
+        db.save().unwrap();
+        db.delete_rows(&[RowId(1)]);
+        let moto = db.add_string("name", "moto");
+        db.enable_wal("testdb-wal-dirty.wal").unwrap();
+        db.add_string("name", "bici");
 
-        let predicates1 = vec![Predicate::new_equal_string("set", "es-en")];
-        let predicates2 = vec![
-            Predicate::new_equal_string("set", "es-en"),
-            Predicate::new_equal_string("name", "disfrutar"),
-        ];
+        let restored = Db::replay_wal("testdb-wal-dirty", "testdb-wal-dirty.wal").unwrap();
+        assert!(restored.diff(&db).is_empty());
+        assert_eq!(restored.row_max(), 4);
+        assert_eq!(
+            restored.get_string(moto, "name"),
+            Some(String::from("moto"))
+        );
+    }
 
-        let row_ids = db.find_row_ids_by_predicate(&predicates1, None);
-        assert_eq!(row_ids, vec![RowId(1), RowId(2)]);
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_wal_reports_failed_writes() {
+        let _ = std::fs::remove_file("save/testdb-wal-full.wal");
+        std::os::unix::fs::symlink("/dev/full", "save/testdb-wal-full.wal").unwrap();
+        let mut db = new_db_with_entries("testdb-wal-full");
+        assert!(db.enable_wal("testdb-wal-full.wal").is_err());
 
-        let row_ids = db.find_row_ids_by_predicate(&predicates2, None);
-        assert_eq!(row_ids, vec![RowId(1)]);
+        db.save().unwrap();
+        db.enable_wal("testdb-wal-full.wal").unwrap();
+        assert!(db.check_wal().is_ok());
+        db.add_string("name", "moto");
+        db.add_string("name", "bici");
+        assert!(matches!(db.check_wal(), Err(DbError::Io(_))));
+        assert!(db.check_wal().is_ok());
     }
 
     #[test]
-    fn find_entries_by_predicate() {
-        let name = "testdb";
-        let db = new_db_with_entries(name);
-
-        let predicates = vec![Predicate::new_equal_string("set", "es-en")];
+    fn column_names_and_counts() {
+        let mut db = new_db_with_entries("testdb");
+        assert_eq!(db.column_names(), ["name", "set", "value"]);
+        let _row_id = db.add_row(vec![
+            Entry::new_string("name", "moto"),
+            Entry::new_i32("add_counter", 1),
+        ]);
+        let expected = [("set", 2), ("name", 3), ("value", 2), ("add_counter", 1)]
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect::<HashMap<String, usize>>();
+        assert_eq!(db.column_counts(), expected);
 
-        let result1 = vec![
-            vec![Entry {
-                name: String::from("name"),
-                value: Db::db_string("disfrutar"),
-            }],
-            vec![Entry {
-                name: String::from("name"),
-                value: Db::db_string("coche"),
-            }],
-        ];
+        db.remove_by_name(RowId(3), "add_counter");
+        assert_eq!(db.column_names(), ["name", "set", "value"]);
+    }
 
-        let result2 = vec![
-            vec![
-                Entry {
-                    name: String::from("name"),
-                    value: Db::db_string("disfrutar"),
-                },
-                Entry {
-                    name: String::from("value"),
-                    value: Db::db_string("to enjoy"),
-                },
-            ],
-            vec![
-                Entry {
-                    name: String::from("name"),
-                    value: Db::db_string("coche"),
-                },
-                Entry {
-                    name: String::from("value"),
-                    value: Db::db_string("car"),
-                },
-            ],
-        ];
+    #[test]
+    fn equal_datetime_uses_index() {
+        let mut db = Db::new("testdb");
+        let day = |d| {
+            NaiveDateTime::parse_from_str(
+                &format!("2018-01-{:02} 10:00:00", d),
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .unwrap()
+        };
+        for i in 0..30 {
+            db.add_row(vec![
+                Entry::new_string("name", &format!("word{}", i)),
+                Entry::new_datetime("add_date", day(i % 3 + 1)),
+            ]);
+        }
+        let predicate = Predicate::new_equal_datetime("add_date", day(2));
+        assert!(db.by_value.contains_key(&predicate.entry));
 
-        let result = db.find_entries_by_predicate(&predicates, &vec!["name"]);
-        assert_eq!(result, result1);
+        let mut scanned = db
+            .iter_rows()
+            .filter(|(_row_id, entries)| Entry::compare_all(entries, &predicate))
+            .map(|(row_id, _entries)| row_id)
+            .collect::<Vec<RowId>>();
+        scanned.sort();
+        assert_eq!(scanned.len(), 10);
 
-        let result = db.find_entries_by_predicate(&predicates, &vec!["name", "value"]);
-        assert_eq!(result, result2);
+        let mut found = db.find_by_predicate(&predicate);
+        found.sort();
+        assert_eq!(found, scanned);
+        assert_eq!(db.find_row_ids_by_predicate(&[predicate], None), scanned);
+        assert!(db
+            .find_by_predicate(&Predicate::new_equal_datetime("add_date", day(4)))
+            .is_empty());
     }
 
     #[test]
-    fn load_and_save() {
-        let name = "testdb";
-        let mut db = new_db_with_entries(name);
-        db.save().unwrap();
-        let db = Db::load(name).unwrap();
-        check_single_entries(&db);
+    fn row_ids_sorted_by_datetime() {
+        let mut db = Db::new("testdb");
+        let dated = |db: &mut Db, word: &str, date: &str| {
+            db.add_row(vec![
+                Entry::new_string("name", word),
+                Entry::new_datetime_str("add_date", date).unwrap(),
+            ])
+        };
+        let middle = dated(&mut db, "coche", "2018-03-01 10:00:00");
+        let undated = db.add_string("name", "moto");
+        let newest = dated(&mut db, "barco", "2018-05-01 10:00:00");
+        let oldest = dated(&mut db, "tren", "2018-01-01 10:00:00");
+
+        assert_eq!(
+            db.row_ids_sorted_by_datetime("add_date", true),
+            [newest, middle, oldest, undated]
+        );
+        assert_eq!(
+            db.row_ids_sorted_by_datetime("add_date", false),
+            [oldest, middle, newest, undated]
+        );
     }
 
     #[test]
-    fn add_row() {
-        let db = new_db_with_entries("testdb");
-        check_single_entries(&db);
+    fn values_by_name() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row_id_entry(RowId(2), Entry::new_string("value", "automobile"));
+        db.add_row_id_entry(RowId(2), Entry::new_string("value", "auto"));
+        assert_eq!(
+            db.values_by_name(RowId(2), "value"),
+            [
+                Db::db_string("car"),
+                Db::db_string("automobile"),
+                Db::db_string("auto")
+            ]
+        );
+        assert_eq!(
+            db.values_by_name(RowId(1), "value"),
+            [Db::db_string("to enjoy")]
+        );
+        assert!(db.values_by_name(RowId(1), "missing").is_empty());
+        assert!(db.values_by_name(RowId(99), "value").is_empty());
     }
 
     #[test]
-    fn data_types() {
-        let t = "Test";
-        assert_eq!(Data::DbString(String::from(t)), Db::db_string(t));
-        let t = 42;
-        assert_eq!(Data::DbI32(t), Db::db_i32(t));
-        let fmt = "%Y-%m-%d %H:%M:%S";
-        let t = "2013-11-22 12:00:00";
-        let dt = NaiveDateTime::parse_from_str(t, fmt).unwrap();
-        assert_eq!(Data::DbDateTime(dt), Db::db_datetime(t).unwrap());
+    fn base64() {
+        for (bytes, encoded) in &[
+            (&b""[..], ""),
+            (&b"f"[..], "Zg=="),
+            (&b"fo"[..], "Zm8="),
+            (&b"foo"[..], "Zm9v"),
+            (&b"foob"[..], "Zm9vYg=="),
+            (&b"\xff\x00\xfe"[..], "/wD+"),
+        ] {
+            assert_eq!(super::base64::encode(bytes), *encoded);
+            assert_eq!(super::base64::decode(encoded).as_deref(), Some(*bytes));
+        }
+        assert_eq!(super::base64::decode("Zg="), None);
+        assert_eq!(super::base64::decode("Z=g="), None);
+        assert_eq!(super::base64::decode("Zg==Zg=="), None);
+        assert_eq!(super::base64::decode("Z!=="), None);
     }
 
     #[test]
-    fn add_or_update_entry_add() {
-        let mut db = new_db_with_entries("testdb");
-
-        println!("Before update/add: {:?}", db.debug_rows(&vec![RowId(2)]));
-        db.add_or_update_entry(
-            RowId(2),
+    fn bytes_round_trip() {
+        let clip = (0..=255).collect::<Vec<u8>>();
+        let mut db = Db::new("testdb-bytes");
+        let row_id = db.add_row(vec![
+            Entry::new_string("name", "coche"),
             Entry {
-                name: String::from("new entry"),
-                value: Db::db_string("new entry content"),
+                name: String::from("audio"),
+                value: Db::db_bytes(&clip),
             },
-        );
-        println!("After update/add: {:?}", db.debug_rows(&vec![RowId(2)]));
+        ]);
+        db.save().unwrap();
+        let contents = std::fs::read_to_string("save/testdb-bytes").unwrap();
+        assert!(contents.contains(&super::base64::encode(&clip)));
 
-        for (i, row) in db.by_row_id.iter().enumerate() {
-            println!("row: {} {:?}", i, row);
-        }
-        assert_eq!(db.find_first_row_id_by_name("new entry"), Some(RowId(2)));
+        let db = Db::load("testdb-bytes").unwrap();
+        assert_eq!(db.first_value(row_id, "audio"), Some(&Data::DbBytes(clip)));
+        assert_eq!(Db::db_bytes(b"abc").to_string(), "<3 bytes>");
+        assert_eq!(Db::db_bytes(b"abc").kind(), DataKind::Bytes);
+        assert!(db
+            .find_row_ids_by_predicate(&[Predicate::new_contains("audio", "")], None)
+            .is_empty());
+    }
+
+    #[test]
+    fn upsert_row_by_inserts() {
+        let mut db = new_db_with_entries("testdb");
+        let row_id = db.upsert_row_by(
+            "name",
+            vec![
+                Entry::new_string("set", "es-en"),
+                Entry::new_string("name", "moto"),
+                Entry::new_string("value", "motorbike"),
+            ],
+        );
+        assert_eq!(row_id, RowId(3));
+        assert_eq!(db.row_count(), 3);
         assert_eq!(
-            db.find_first_row_id_by_value("new entry", &Db::db_string("new entry content")),
-            Some(RowId(2))
+            db.get_string(row_id, "value"),
+            Some(String::from("motorbike"))
         );
+
+        // Without a key entry there is nothing to match
+        let row_id = db.upsert_row_by("name", vec![Entry::new_string("value", "car")]);
+        assert_eq!(row_id, RowId(4));
     }
 
     #[test]
-    fn add_or_update_entry_update() {
+    fn upsert_row_by_updates() {
         let mut db = new_db_with_entries("testdb");
-
-        println!("{:?}", db.debug_rows(&vec![RowId(2)]));
-        db.add_or_update_entry(
-            RowId(2),
-            Entry {
-                name: String::from("new entry"),
-                value: Db::db_string("new entry content"),
-            },
+        let row_id = db.upsert_row_by(
+            "name",
+            vec![
+                Entry::new_string("name", "coche"),
+                Entry::new_string("value", "automobile"),
+                Entry::new_string("value", "auto"),
+                Entry::new_i32("add_counter", 1),
+            ],
         );
-        db.add_or_update_entry(
-            RowId(2),
-            Entry {
-                name: String::from("new entry"),
-                value: Db::db_string("new entry content updated"),
-            },
+        assert_eq!(row_id, RowId(2));
+        assert_eq!(db.row_count(), 2);
+        assert_eq!(
+            db.values_by_name(row_id, "value"),
+            [Db::db_string("automobile"), Db::db_string("auto")]
         );
-        println!("{:?}", db.debug_rows(&vec![RowId(2)]));
-        for (row_id, entries) in db.by_row_id.iter() {
-            println!("{:?}", row_id);
-            for entry in entries {
-                println!("    {:?}", entry);
-            }
+        assert_eq!(db.get_i32(row_id, "add_counter"), Some(1));
+        assert_eq!(db.get_string(row_id, "set"), Some(String::from("es-en")));
+        assert_eq!(db.values_by_name(row_id, "name"), [Db::db_string("coche")]);
+        assert!(db
+            .find_row_ids_by_value("value", &Db::db_string("car"))
+            .is_empty());
+    }
+
+    #[test]
+    fn page_sorted() {
+        let mut db = Db::new("testdb");
+        for (word, counter) in &[
+            ("moto", 3),
+            ("coche", 1),
+            ("barco", 4),
+            ("tren", 2),
+            ("bici", 5),
+        ] {
+            db.add_row(vec![
+                Entry::new_string("name", word),
+                Entry::new_i32("add_counter", *counter),
+            ]);
         }
+        let _unrelated = db.add_i32("add_counter", 0);
+        let predicates = [Predicate::new_any("name")];
+
+        let page = db.page_sorted(&predicates, "add_counter", 1, 2);
         assert_eq!(
-            db.find_first_row_id_by_value("new entry", &Db::db_string("new entry content updated")),
-            Some(RowId(2))
+            page,
+            [
+                [
+                    Entry::new_string("name", "tren"),
+                    Entry::new_i32("add_counter", 2)
+                ],
+                [
+                    Entry::new_string("name", "moto"),
+                    Entry::new_i32("add_counter", 3)
+                ],
+            ]
         );
+        let names = db
+            .page_sorted(&predicates, "name", 0, 10)
+            .iter()
+            .map(|row| row[0].value.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["barco", "bici", "coche", "moto", "tren"]);
+        assert!(db.page_sorted(&predicates, "name", 5, 10).is_empty());
     }
 
     #[test]
-    fn delete_entry_all() {
-        let mut db = new_db_with_entries("testdb");
-
-        let mut add_row = |n| {
-            if let Some(_entry) = db.by_row_id.get(&RowId(n)) {
-                println!("{:?}", db.debug_rows(&vec![RowId(n)]));
-            }
-            db.add_row_id_entry(
-                RowId(n),
-                Entry {
-                    name: String::from("new entry"),
-                    value: Db::db_string(&format!("new col for row {}", n)),
-                },
-            );
-        };
-        add_row(1);
-        add_row(2);
-        add_row(3);
-        for (row_id, entries) in db.by_row_id.iter() {
-            println!("{:?}", row_id);
-            for entry in entries {
-                println!("    {:?}", entry);
-            }
+    fn equal_i32_uses_index() {
+        let mut db = Db::new("testdb");
+        for i in 0..30 {
+            db.add_row(vec![
+                Entry::new_string("name", &format!("word{}", i)),
+                Entry::new_i32("add_counter", i % 5),
+            ]);
         }
+        let _string_row = db.add_string("add_counter", "3");
+        let _i64_row = db.add_row(vec![Entry::new_i64("add_counter", 3)]);
+        let predicate = Predicate::new_equal_i32("add_counter", 3);
+        assert_eq!(predicate.entry, Entry::new_i32("add_counter", 3));
+        assert_eq!(db.by_value[&predicate.entry].len(), 6);
 
-        let row_ids = db.find_row_ids_by_name("new entry");
-        assert_eq!(row_ids.len(), 3);
-        assert!(row_ids.contains(&RowId(1)));
-        assert!(row_ids.contains(&RowId(2)));
-        assert!(row_ids.contains(&RowId(3)));
+        let mut scanned = db
+            .iter_rows()
+            .filter(|(_row_id, entries)| Entry::compare_all(entries, &predicate))
+            .map(|(row_id, _entries)| row_id)
+            .collect::<Vec<RowId>>();
+        scanned.sort();
+        assert_eq!(
+            scanned,
+            [
+                RowId(4),
+                RowId(9),
+                RowId(14),
+                RowId(19),
+                RowId(24),
+                RowId(29)
+            ]
+        );
+        let found = db.find_row_ids_by_predicate(&[predicate], None);
+        assert_eq!(found, scanned);
+        assert_eq!(
+            db.find_row_ids_by_predicate(
+                &[
+                    Predicate::new_equal_i32("add_counter", 3),
+                    Predicate::new_equal_string("name", "word8")
+                ],
+                None
+            ),
+            [RowId(9)]
+        );
+        assert!(db
+            .find_row_ids_by_predicate(&[Predicate::new_equal_i32("add_counter", 7)], None)
+            .is_empty());
+    }
 
-        println!("Deleting entries...");
-        db.delete_entry_all("new entry");
+    #[test]
+    fn iter_entries() {
+        let mut db = new_db_with_entries("testdb");
+        db.add_row_id_entry(RowId(2), Entry::new_string("value", "automobile"));
+        assert_eq!(db.iter_entries().count(), db.entry_count());
+        assert_eq!(db.iter_entries().count(), 7);
+        let row_2 = db
+            .iter_entries()
+            .filter(|(row_id, _entry)| *row_id == RowId(2))
+            .map(|(_row_id, entry)| entry.clone())
+            .collect::<Vec<Entry>>();
+        assert_eq!(row_2, db.row_entries(RowId(2)).unwrap());
+    }
 
-        for (row_id, entries) in db.by_row_id.iter() {
-            println!("{:?}", row_id);
-            for entry in entries {
-                println!("    {:?}", entry);
-            }
-        }
+    #[test]
+    fn save_and_load_namespaced() {
+        let filename = "testdb-namespaced";
+        let _ = std::fs::remove_file("save/testdb-namespaced");
+        let vocabulary = new_db_with_entries("vocabulary");
+        let mut personal = Db::new("personal");
+        personal.add_row(vec![
+            Entry::new_string("name", "coche"),
+            Entry::new_i32("add_counter", 3),
+        ]);
+        vocabulary.save_namespaced("vocabulary", filename).unwrap();
+        personal.save_namespaced("personal", filename).unwrap();
 
-        let row_ids = db.find_row_ids_by_name("new entry");
-        assert_eq!(row_ids.len(), 0);
+        let loaded = Db::load_namespaced("vocabulary", filename).unwrap();
+        check_single_entries(&loaded);
+        assert!(loaded.diff(&vocabulary).is_empty());
+        let loaded = Db::load_namespaced("personal", filename).unwrap();
+        assert!(loaded.diff(&personal).is_empty());
+        assert_eq!(loaded.get_i32(RowId(1), "add_counter"), Some(3));
 
-        let row_ids = db.find_row_ids_by_name("set");
-        assert_eq!(row_ids.len(), 2);
-        assert!(row_ids.contains(&RowId(1)));
-        assert!(row_ids.contains(&RowId(2)));
-        assert!(!row_ids.contains(&RowId(3)));
+        // Saving a namespace again replaces it and keeps the others
+        personal.update_value(RowId(1), "add_counter", Db::db_i32(4));
+        personal.save_namespaced("personal", filename).unwrap();
+        let loaded = Db::load_namespaced("personal", filename).unwrap();
+        assert_eq!(loaded.get_i32(RowId(1), "add_counter"), Some(4));
+        assert_eq!(
+            Db::load_namespaced("vocabulary", filename)
+                .unwrap()
+                .row_count(),
+            2
+        );
 
-        let row_ids = db.find_row_ids_by_name("name");
-        assert_eq!(row_ids.len(), 2);
-        assert!(row_ids.contains(&RowId(1)));
-        assert!(row_ids.contains(&RowId(2)));
-        assert!(!row_ids.contains(&RowId(3)));
+        assert_eq!(
+            Db::load_namespaced("missing", filename)
+                .unwrap()
+                .row_count(),
+            0
+        );
+        assert_eq!(
+            Db::load_namespaced("missing", "testdb-namespaced-none")
+                .unwrap()
+                .row_count(),
+            0
+        );
     }
 
     #[test]
-    fn find_all_row_ids() {
-        let db = new_db_with_entries("testdb");
-        let row_ids = db.find_all_row_ids();
-        assert_eq!(row_ids.len(), 2);
-        assert!(row_ids.contains(&RowId(1)));
-        assert!(row_ids.contains(&RowId(2)));
-    }
+    fn enable_trim() {
+        let mut db = Db::new("testdb");
+        let untrimmed = db.add_string("value", "  car  ");
+        db.enable_trim();
+        let row_id = db.add_string("value", "  car  ");
+        db.add_row_id_entry(row_id, Entry::new_string("value", "\tauto\n"));
+        let _number = db.add_i32("value", 1);
 
-    #[test]
-    fn find_row_ids_by_value() {
-        let db = new_db_with_entries("testdb");
-        let entry = Entry::new_string("name", "coche");
-        let row_id = db.by_value[&entry].iter().next().unwrap();
-        let entry_new = db.by_row_id[row_id]
-            .iter()
-            .filter(|entry| &entry.name == "name")
-            .next()
-            .unwrap();
-        assert_eq!(&entry, entry_new);
+        assert_eq!(
+            db.find_row_ids_by_predicate(&[Predicate::new_equal_string("value", "car")], None),
+            [row_id]
+        );
+        assert_eq!(
+            db.find_row_ids_by_value("value", &Db::db_string("auto")),
+            [row_id]
+        );
+        assert_eq!(
+            db.get_string(untrimmed, "value"),
+            Some(String::from("  car  "))
+        );
+        assert!(!db.append_value(row_id, "value", Db::db_string(" auto ")));
     }
 }